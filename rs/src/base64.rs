@@ -0,0 +1,151 @@
+//! Base64 encoding and decoding (RFC 4648)
+
+use alloc::vec::Vec;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const PAD: u8 = b'=';
+
+/// Encode bytes into a base64 string with padding
+pub fn encode(input: &[u8]) -> String {
+    let mut s = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let b0 = chunk[0] as u32;
+        let b1 = chunk.get(1).copied().unwrap_or(0) as u32;
+        let b2 = chunk.get(2).copied().unwrap_or(0) as u32;
+        let acc = (b0 << 16) | (b1 << 8) | b2;
+
+        s.push(ALPHABET[(acc >> 18) as usize & 0x3F] as char);
+        s.push(ALPHABET[(acc >> 12) as usize & 0x3F] as char);
+        if chunk.len() > 1 {
+            s.push(ALPHABET[(acc >> 6) as usize & 0x3F] as char);
+        } else {
+            s.push(PAD as char);
+        }
+        if chunk.len() > 2 {
+            s.push(ALPHABET[acc as usize & 0x3F] as char);
+        } else {
+            s.push(PAD as char);
+        }
+    }
+    s
+}
+
+/// Decode a base64 string, ASCII whitespace is ignored
+///
+/// The padding may be omitted, but if present it must complete the last group of four characters.
+/// Both the standard and the URL-safe alphabets are accepted.
+pub fn decode(input: &str) -> Option<Vec<u8>> {
+    let mut vec = Vec::with_capacity(input.len() / 4 * 3);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    let mut symbols = 0usize;
+    let mut padding = 0usize;
+    for ch in input.bytes() {
+        let value = match ch {
+            b'A'..=b'Z' => ch - b'A',
+            b'a'..=b'z' => ch - b'a' + 26,
+            b'0'..=b'9' => ch - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            PAD => {
+                padding += 1;
+                continue;
+            }
+            _ if ch.is_ascii_whitespace() => continue,
+            _ => return None,
+        };
+        if padding > 0 {
+            // data after padding
+            return None;
+        }
+        acc = (acc << 6) | value as u32;
+        bits += 6;
+        symbols += 1;
+        if bits >= 8 {
+            bits -= 8;
+            vec.push((acc >> bits) as u8);
+        }
+    }
+    let remainder = symbols % 4;
+    // a single remaining character cannot represent a byte
+    if remainder == 1 {
+        return None;
+    }
+    if padding > 0 && (remainder == 0 || remainder + padding != 4) {
+        return None;
+    }
+    // the unused bits of the last character must be zero
+    if acc & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some(vec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vectors() {
+        // RFC 4648 section 10
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (plain, encoded) in vectors {
+            assert_eq!(encode(plain.as_bytes()), encoded);
+            assert_eq!(decode(encoded).unwrap(), plain.as_bytes());
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = (0..=255).rev().collect::<Vec<u8>>();
+        for len in 0..=bytes.len() {
+            let input = &bytes[..len];
+            let encoded = encode(input);
+            assert_eq!(encoded.len() % 4, 0);
+            assert_eq!(decode(&encoded).unwrap(), input, "{len}");
+            // without padding
+            assert_eq!(
+                decode(encoded.trim_end_matches('=')).unwrap(),
+                input,
+                "{len}"
+            );
+        }
+    }
+
+    #[test]
+    fn lenient_input() {
+        assert_eq!(decode(" Zm9v\r\nYmFy\n").unwrap(), b"foobar");
+        assert_eq!(decode("-_-_").unwrap(), decode("+/+/").unwrap());
+    }
+
+    #[test]
+    fn malformed_input() {
+        for input in [
+            "Z",
+            "Z===",
+            "Zg=",
+            "Zg===",
+            "Zm8==",
+            "Zm9v=",
+            "Zm9v====",
+            "=",
+            "==Zg",
+            "Zg==Zg==",
+            "Zm=8",
+            "Zh==",
+            "Zm9=",
+            "Zm9v*",
+            "Zm9vYg=\n=x",
+        ] {
+            assert_eq!(decode(input), None, "{input:?}");
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Color space conversions and color adjustments
+
+use crate::{MAGIC_NUMBER, gamma::srgb_to_linear, image_buffer, image_info, luminance};
+use wasm_bindgen::prelude::*;
+
+/// Convert RGB (`0.0..=1.0`) to HSL, the hue is in degrees (`0.0..360.0`)
+pub fn rgb_to_hsl(rgb: [f64; 3]) -> [f64; 3] {
+    let [r, g, b] = rgb;
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+    let d = max - min;
+    if d == 0.0 {
+        return [0.0, 0.0, l];
+    }
+    let s = d / (1.0 - (2.0 * l - 1.0).abs());
+    [hue(rgb, max, d), s, l]
+}
+
+/// Convert HSL to RGB (`0.0..=1.0`)
+pub fn hsl_to_rgb(hsl: [f64; 3]) -> [f64; 3] {
+    let [h, s, l] = hsl;
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    from_chroma(h, c, l - c / 2.0)
+}
+
+/// Convert RGB (`0.0..=1.0`) to HSV, the hue is in degrees (`0.0..360.0`)
+pub fn rgb_to_hsv(rgb: [f64; 3]) -> [f64; 3] {
+    let [r, g, b] = rgb;
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let d = max - min;
+    if d == 0.0 {
+        return [0.0, 0.0, max];
+    }
+    [hue(rgb, max, d), d / max, max]
+}
+
+/// Convert HSV to RGB (`0.0..=1.0`)
+pub fn hsv_to_rgb(hsv: [f64; 3]) -> [f64; 3] {
+    let [h, s, v] = hsv;
+    let c = v * s;
+    from_chroma(h, c, v - c)
+}
+
+/// Convert sRGB (`0.0..=1.0`) to CIE L\*a\*b\* with the D65 white point
+pub fn rgb_to_lab(rgb: [f64; 3]) -> [f64; 3] {
+    let [r, g, b] = rgb.map(srgb_to_linear);
+    let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047;
+    let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+    let z = (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / 1.08883;
+    let f = |t: f64| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+#[inline]
+fn hue(rgb: [f64; 3], max: f64, d: f64) -> f64 {
+    let [r, g, b] = rgb;
+    let h = if max == r {
+        (g - b) / d
+    } else if max == g {
+        (b - r) / d + 2.0
+    } else {
+        (r - g) / d + 4.0
+    };
+    (h * 60.0).rem_euclid(360.0)
+}
+
+#[inline]
+fn from_chroma(h: f64, c: f64, m: f64) -> [f64; 3] {
+    let h = h.rem_euclid(360.0) / 60.0;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    [r + m, g + m, b + m]
+}
+
+#[inline]
+fn to_unit(pixel: &[u8]) -> [f64; 3] {
+    [
+        pixel[0] as f64 / 255.0,
+        pixel[1] as f64 / 255.0,
+        pixel[2] as f64 / 255.0,
+    ]
+}
+
+#[inline]
+fn from_unit(pixel: &mut [u8], rgb: [f64; 3]) {
+    for (channel, value) in pixel.iter_mut().zip(rgb) {
+        *channel = (value * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+}
+
+/// Apply a function to the color of each pixel of the current image
+fn map_colors<F: FnMut([f64; 3]) -> [f64; 3]>(mut f: F) {
+    for pixel in image_buffer().chunks_exact_mut(MAGIC_NUMBER) {
+        let rgb = f(to_unit(pixel));
+        from_unit(pixel, rgb);
+    }
+}
+
+/// Rotate the hue by the specified degrees
+#[wasm_bindgen]
+pub fn hue_rotate(degrees: f64) -> bool {
+    if !degrees.is_finite() {
+        return false;
+    }
+    map_colors(|rgb| {
+        let [h, s, l] = rgb_to_hsl(rgb);
+        hsl_to_rgb([h + degrees, s, l])
+    });
+    true
+}
+
+/// Scale the saturation, `0.0` is gray and `1.0` is unchanged
+#[wasm_bindgen]
+pub fn saturate(factor: f64) -> bool {
+    if !(factor.is_finite() && factor >= 0.0) {
+        return false;
+    }
+    map_colors(|rgb| {
+        let [h, s, l] = rgb_to_hsl(rgb);
+        hsl_to_rgb([h, (s * factor).min(1.0), l])
+    });
+    true
+}
+
+/// Increase the saturation of dull colors more than that of vivid colors (`-1.0` to `1.0`)
+#[wasm_bindgen]
+pub fn vibrance(amount: f64) -> bool {
+    if !(-1.0..=1.0).contains(&amount) {
+        return false;
+    }
+    map_colors(|rgb| {
+        let [h, s, v] = rgb_to_hsv(rgb);
+        let s = s * (1.0 + amount * (1.0 - s));
+        hsv_to_rgb([h, s.clamp(0.0, 1.0), v])
+    });
+    true
+}
+
+/// Shift the lightness toward white (positive) or black (negative), `-1.0` to `1.0`
+#[wasm_bindgen]
+pub fn lightness(amount: f64) -> bool {
+    if !(-1.0..=1.0).contains(&amount) {
+        return false;
+    }
+    map_colors(|rgb| {
+        let [h, s, l] = rgb_to_hsl(rgb);
+        let l = if amount >= 0.0 {
+            l + (1.0 - l) * amount
+        } else {
+            l * (1.0 + amount)
+        };
+        hsl_to_rgb([h, s, l])
+    });
+    true
+}
+
+/// Tint the image to a single hue, keeping the luminance of each pixel
+///
+/// `saturation` is `0.0` to `1.0`.
+#[wasm_bindgen]
+pub fn colorize(hue: f64, saturation: f64) -> bool {
+    if !hue.is_finite() || !(0.0..=1.0).contains(&saturation) {
+        return false;
+    }
+    image_info().is_grayscale = saturation == 0.0;
+    for pixel in image_buffer().chunks_exact_mut(MAGIC_NUMBER) {
+        let l = luminance(pixel) as f64 / 255.0;
+        from_unit(pixel, hsl_to_rgb([hue, saturation, l]));
+    }
+    true
+}
+
+/// Shift the colors of shadows, midtones and highlights separately
+///
+/// Each argument is the shift of cyan-red, magenta-green and yellow-blue (`-1.0` to `1.0`).
+/// If `preserve_luminosity` is specified, the lightness of each pixel is kept.
+#[wasm_bindgen]
+pub fn color_balance(
+    shadows: &[f64],
+    midtones: &[f64],
+    highlights: &[f64],
+    preserve_luminosity: bool,
+) -> bool {
+    let (Ok(shadows), Ok(midtones), Ok(highlights)) = (
+        <[f64; 3]>::try_from(shadows),
+        <[f64; 3]>::try_from(midtones),
+        <[f64; 3]>::try_from(highlights),
+    ) else {
+        return false;
+    };
+    if [shadows, midtones, highlights]
+        .iter()
+        .flatten()
+        .any(|v| !(-1.0..=1.0).contains(v))
+    {
+        return false;
+    }
+
+    image_info().is_grayscale = false;
+    map_colors(|rgb| {
+        let l = rgb_to_hsl(rgb)[2];
+        // Weights of each tonal range by the lightness
+        let shadow_weight = (1.0 - l * 4.0).clamp(0.0, 1.0) * 0.7 + (1.0 - l) * 0.3;
+        let highlight_weight = (l * 4.0 - 3.0).clamp(0.0, 1.0) * 0.7 + l * 0.3;
+        let midtone_weight = (1.0 - (l - 0.5).abs() * 2.0).clamp(0.0, 1.0);
+
+        let mut result = rgb;
+        for (ch, value) in result.iter_mut().enumerate() {
+            let shift = shadows[ch] * shadow_weight
+                + midtones[ch] * midtone_weight
+                + highlights[ch] * highlight_weight;
+            *value = (*value + shift * 0.5).clamp(0.0, 1.0);
+        }
+        if preserve_luminosity {
+            let [h, s, _] = rgb_to_hsl(result);
+            result = hsl_to_rgb([h, s, l]);
+        }
+        result
+    });
+    true
+}
@@ -0,0 +1,328 @@
+//! Noise reduction filters
+
+use crate::{ImageInfo, MAGIC_NUMBER, image_buffer, image_info};
+use alloc::vec::Vec;
+use wasm_bindgen::prelude::*;
+
+/// Larger radii of the median filter use sliding histograms instead of sorting
+const MEDIAN_SORT_MAX_RADIUS: u32 = 2;
+
+/// Replace each channel with the median of the square of `radius * 2 + 1` pixels on a side
+///
+/// Effective against impulse noise, and keeps the edges sharp.
+#[wasm_bindgen]
+pub fn median_filter(radius: u32) -> bool {
+    let info = image_info();
+    let ib = image_buffer();
+    let Some(ob) = median_filter_buffer(info, ib, radius) else {
+        return false;
+    };
+    ib.copy_from_slice(&ob);
+    crate::update_transparency(info, ib);
+    true
+}
+
+/// Smooth the image while keeping the edges
+///
+/// `strength` is the standard deviation of the color difference (`0` to `255`) regarded as noise.
+#[wasm_bindgen]
+pub fn bilateral_filter(radius: u32, strength: f64) -> bool {
+    let info = image_info();
+    let ib = image_buffer();
+    let Some(ob) = bilateral_filter_buffer(info, ib, radius, strength) else {
+        return false;
+    };
+    ib.copy_from_slice(&ob);
+    crate::update_transparency(info, ib);
+    true
+}
+
+/// Non-local means denoising
+///
+/// Each pixel is averaged with the pixels in `search_radius` whose neighborhood of `patch_radius` looks similar.
+/// `strength` is the color difference (`0` to `255`) regarded as noise.
+#[wasm_bindgen]
+pub fn non_local_means(search_radius: u32, patch_radius: u32, strength: f64) -> bool {
+    let info = image_info();
+    let ib = image_buffer();
+    let Some(ob) = non_local_means_buffer(info, ib, search_radius, patch_radius, strength) else {
+        return false;
+    };
+    ib.copy_from_slice(&ob);
+    crate::update_transparency(info, ib);
+    true
+}
+
+/// Pixel accessor that replicates the pixels on the edges
+#[inline]
+fn offset_clamped(info: &ImageInfo, x: isize, y: isize) -> usize {
+    let x = x.clamp(0, info.width as isize - 1) as usize;
+    let y = y.clamp(0, info.height as isize - 1) as usize;
+    (x + y * info.width as usize) * MAGIC_NUMBER
+}
+
+fn alloc_buffer(len: usize) -> Option<Vec<u8>> {
+    let mut ob = Vec::new();
+    ob.try_reserve(len).ok()?;
+    Some(ob)
+}
+
+pub fn median_filter_buffer(info: &ImageInfo, ib: &[u8], radius: u32) -> Option<Vec<u8>> {
+    if radius <= MEDIAN_SORT_MAX_RADIUS {
+        median_sort(info, ib, radius)
+    } else {
+        median_histogram(info, ib, radius)
+    }
+}
+
+fn median_sort(info: &ImageInfo, ib: &[u8], radius: u32) -> Option<Vec<u8>> {
+    let r = radius as isize;
+    let mut ob = alloc_buffer(ib.len())?;
+    let mut window = Vec::with_capacity(((r * 2 + 1) * (r * 2 + 1)) as usize);
+    for y in 0..info.height as isize {
+        for x in 0..info.width as isize {
+            for ch in 0..MAGIC_NUMBER {
+                window.clear();
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        window.push(ib[offset_clamped(info, x + dx, y + dy) + ch]);
+                    }
+                }
+                let mid = window.len() / 2;
+                ob.push(*window.select_nth_unstable(mid).1);
+            }
+        }
+    }
+    Some(ob)
+}
+
+/// Huang's algorithm, the histograms of the window are updated column by column
+fn median_histogram(info: &ImageInfo, ib: &[u8], radius: u32) -> Option<Vec<u8>> {
+    let r = radius as isize;
+    let mid = ((r * 2 + 1) * (r * 2 + 1) / 2) as u32;
+    let mut ob = alloc_buffer(ib.len())?;
+    let mut histograms = [[0u32; 256]; MAGIC_NUMBER];
+    for y in 0..info.height as isize {
+        for histogram in histograms.iter_mut() {
+            histogram.fill(0);
+        }
+        let update_column = |histograms: &mut [[u32; 256]; MAGIC_NUMBER], x: isize, add: bool| {
+            for dy in -r..=r {
+                let offset = offset_clamped(info, x, y + dy);
+                for (ch, histogram) in histograms.iter_mut().enumerate() {
+                    let bin = &mut histogram[ib[offset + ch] as usize];
+                    if add {
+                        *bin += 1;
+                    } else {
+                        *bin -= 1;
+                    }
+                }
+            }
+        };
+        for dx in -r..=r {
+            update_column(&mut histograms, dx, true);
+        }
+        for x in 0..info.width as isize {
+            if x > 0 {
+                update_column(&mut histograms, x - r - 1, false);
+                update_column(&mut histograms, x + r, true);
+            }
+            for histogram in histograms.iter() {
+                let mut count = 0;
+                let median = histogram
+                    .iter()
+                    .position(|v| {
+                        count += v;
+                        count > mid
+                    })
+                    .unwrap_or(0);
+                ob.push(median as u8);
+            }
+        }
+    }
+    Some(ob)
+}
+
+pub fn bilateral_filter_buffer(
+    info: &ImageInfo,
+    ib: &[u8],
+    radius: u32,
+    strength: f64,
+) -> Option<Vec<u8>> {
+    if !(strength.is_finite() && strength > 0.0) {
+        return None;
+    }
+    let r = radius as isize;
+    let sigma_spatial = (radius as f64 / 2.0).max(0.5);
+    let spatial = (-r..=r)
+        .map(|d| (-(d * d) as f64 / (2.0 * sigma_spatial * sigma_spatial)).exp())
+        .collect::<Vec<_>>();
+    let range_scale = -1.0 / (2.0 * strength * strength);
+
+    let mut ob = alloc_buffer(ib.len())?;
+    for y in 0..info.height as isize {
+        for x in 0..info.width as isize {
+            let center = &ib[offset_clamped(info, x, y)..][..MAGIC_NUMBER];
+            let mut acc = [0.0; 4];
+            let mut sum = 0.0;
+            for (dy, wy) in (-r..=r).zip(spatial.iter()) {
+                for (dx, wx) in (-r..=r).zip(spatial.iter()) {
+                    let pixel = &ib[offset_clamped(info, x + dx, y + dy)..][..MAGIC_NUMBER];
+                    let distance = color_distance(center, pixel) as f64;
+                    let w = wx * wy * (distance * range_scale).exp();
+                    // Premultiplied alpha
+                    let alpha = pixel[3] as f64;
+                    for ch in 0..3 {
+                        acc[ch] += pixel[ch] as f64 * alpha * w;
+                    }
+                    acc[3] += alpha * w;
+                    sum += w;
+                }
+            }
+            ob.extend_from_slice(&unpremultiply(acc, sum));
+        }
+    }
+    Some(ob)
+}
+
+pub fn non_local_means_buffer(
+    info: &ImageInfo,
+    ib: &[u8],
+    search_radius: u32,
+    patch_radius: u32,
+    strength: f64,
+) -> Option<Vec<u8>> {
+    if !(strength.is_finite() && strength > 0.0) {
+        return None;
+    }
+    let width = info.width as usize;
+    let height = info.height as usize;
+    let len = width * height;
+    let sr = search_radius as isize;
+    let pr = patch_radius as isize;
+    let h2 = strength * strength;
+
+    // Sums of the weights and the weighted premultiplied pixels
+    let mut acc = Vec::new();
+    acc.try_reserve(len).ok()?;
+    acc.resize(len, [0.0f64; 5]);
+    // Summed-area table of the squared differences between the image and the shifted image
+    let mut table = Vec::new();
+    table.try_reserve((width + 1) * (height + 1)).ok()?;
+    table.resize((width + 1) * (height + 1), 0.0f64);
+
+    for dy in -sr..=sr {
+        for dx in -sr..=sr {
+            for y in 0..height {
+                let mut row_sum = 0.0;
+                for x in 0..width {
+                    let lhs = &ib[(x + y * width) * MAGIC_NUMBER..][..MAGIC_NUMBER];
+                    let rhs = &ib[offset_clamped(info, x as isize + dx, y as isize + dy)..]
+                        [..MAGIC_NUMBER];
+                    row_sum += color_distance(lhs, rhs) as f64;
+                    table[(x + 1) + (y + 1) * (width + 1)] =
+                        table[(x + 1) + y * (width + 1)] + row_sum;
+                }
+            }
+
+            for y in 0..height as isize {
+                for x in 0..width as isize {
+                    // The patch is clamped on the edges of the image
+                    let left = (x - pr).max(0) as usize;
+                    let top = (y - pr).max(0) as usize;
+                    let right = (x + pr + 1).min(width as isize) as usize;
+                    let bottom = (y + pr + 1).min(height as isize) as usize;
+                    let area = ((right - left) * (bottom - top)) as f64;
+                    let distance = (table[right + bottom * (width + 1)]
+                        - table[left + bottom * (width + 1)]
+                        - table[right + top * (width + 1)]
+                        + table[left + top * (width + 1)])
+                        / area
+                        / MAGIC_NUMBER as f64;
+                    let w = (-distance.max(0.0) / h2).exp();
+
+                    let pixel = &ib[offset_clamped(info, x + dx, y + dy)..][..MAGIC_NUMBER];
+                    let alpha = pixel[3] as f64;
+                    let acc = &mut acc[x as usize + y as usize * width];
+                    for ch in 0..3 {
+                        acc[ch] += pixel[ch] as f64 * alpha * w;
+                    }
+                    acc[3] += alpha * w;
+                    acc[4] += w;
+                }
+            }
+        }
+    }
+
+    let mut ob = alloc_buffer(ib.len())?;
+    for acc in acc.iter() {
+        ob.extend_from_slice(&unpremultiply([acc[0], acc[1], acc[2], acc[3]], acc[4]));
+    }
+    Some(ob)
+}
+
+/// Squared distance of two pixels
+#[inline]
+fn color_distance(lhs: &[u8], rhs: &[u8]) -> u32 {
+    lhs.iter()
+        .zip(rhs)
+        .map(|(l, r)| {
+            let d = *l as i32 - *r as i32;
+            (d * d) as u32
+        })
+        .sum()
+}
+
+/// Convert the weighted sums of premultiplied values back into a pixel
+#[inline]
+fn unpremultiply(acc: [f64; 4], sum: f64) -> [u8; 4] {
+    if sum <= 0.0 || acc[3] <= 0.0 {
+        return [0; 4];
+    }
+    let alpha = acc[3] / sum;
+    [
+        (acc[0] / acc[3]).round().clamp(0.0, 255.0) as u8,
+        (acc[1] / acc[3]).round().clamp(0.0, 255.0) as u8,
+        (acc[2] / acc[3]).round().clamp(0.0, 255.0) as u8,
+        alpha.round().clamp(0.0, 255.0) as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Transparency;
+
+    /// Pseudorandom noise over a gradient, with some transparent pixels
+    fn noisy(width: u32, height: u32) -> (ImageInfo, Vec<u8>) {
+        let mut seed = 0x1234_5678u32;
+        let mut ib = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let base = (x * 255 / width) as u8;
+                for _ in 0..MAGIC_NUMBER {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    let noise = (seed >> 16) as u8;
+                    ib.push(if noise % 4 == 0 {
+                        noise
+                    } else {
+                        base ^ (y as u8)
+                    });
+                }
+            }
+        }
+        (ImageInfo::new(width, height, Transparency::Translucent), ib)
+    }
+
+    #[test]
+    fn median_histogram_matches_sort() {
+        for (width, height) in [(23, 17), (5, 40), (1, 1)] {
+            let (info, ib) = noisy(width, height);
+            for radius in [0, 1, 2, 3, 6, 30] {
+                let sorted = median_sort(&info, &ib, radius).unwrap();
+                let histogram = median_histogram(&info, &ib, radius).unwrap();
+                assert!(sorted == histogram, "{width}x{height} radius {radius}");
+            }
+        }
+    }
+}
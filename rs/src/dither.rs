@@ -0,0 +1,422 @@
+//! Dithering engine used by color reduction
+
+use crate::ImageInfo;
+use alloc::vec::Vec;
+use std::sync::LazyLock;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DitherMode {
+    None,
+    FloydSteinberg,
+    Atkinson,
+    JarvisJudiceNinke,
+    Stucki,
+    Burkes,
+    Sierra,
+    TwoRowSierra,
+    SierraLite,
+    Bayer2,
+    Bayer4,
+    Bayer8,
+    BlueNoise,
+}
+
+/// Distribution of the quantization error to the neighboring pixels
+#[derive(Debug, Clone, Copy)]
+pub struct DiffusionKernel {
+    /// Offset of x, offset of y and weight
+    pub weights: &'static [(isize, usize, isize)],
+    pub divisor: isize,
+}
+
+impl DitherMode {
+    pub fn diffusion_kernel(&self) -> Option<DiffusionKernel> {
+        #[rustfmt::skip]
+        let (weights, divisor): (&'static [(isize, usize, isize)], isize) = match self {
+            DitherMode::FloydSteinberg => (&[
+                (1, 0, 7),
+                (-1, 1, 3), (0, 1, 5), (1, 1, 1),
+            ], 16),
+            DitherMode::Atkinson => (&[
+                (1, 0, 1), (2, 0, 1),
+                (-1, 1, 1), (0, 1, 1), (1, 1, 1),
+                (0, 2, 1),
+            ], 8),
+            DitherMode::JarvisJudiceNinke => (&[
+                (1, 0, 7), (2, 0, 5),
+                (-2, 1, 3), (-1, 1, 5), (0, 1, 7), (1, 1, 5), (2, 1, 3),
+                (-2, 2, 1), (-1, 2, 3), (0, 2, 5), (1, 2, 3), (2, 2, 1),
+            ], 48),
+            DitherMode::Stucki => (&[
+                (1, 0, 8), (2, 0, 4),
+                (-2, 1, 2), (-1, 1, 4), (0, 1, 8), (1, 1, 4), (2, 1, 2),
+                (-2, 2, 1), (-1, 2, 2), (0, 2, 4), (1, 2, 2), (2, 2, 1),
+            ], 42),
+            DitherMode::Burkes => (&[
+                (1, 0, 8), (2, 0, 4),
+                (-2, 1, 2), (-1, 1, 4), (0, 1, 8), (1, 1, 4), (2, 1, 2),
+            ], 32),
+            DitherMode::Sierra => (&[
+                (1, 0, 5), (2, 0, 3),
+                (-2, 1, 2), (-1, 1, 4), (0, 1, 5), (1, 1, 4), (2, 1, 2),
+                (-1, 2, 2), (0, 2, 3), (1, 2, 2),
+            ], 32),
+            DitherMode::TwoRowSierra => (&[
+                (1, 0, 4), (2, 0, 3),
+                (-2, 1, 1), (-1, 1, 2), (0, 1, 3), (1, 1, 2), (2, 1, 1),
+            ], 16),
+            DitherMode::SierraLite => (&[
+                (1, 0, 2),
+                (-1, 1, 1), (0, 1, 1),
+            ], 4),
+            _ => return None,
+        };
+        Some(DiffusionKernel { weights, divisor })
+    }
+
+    /// Threshold map for ordered dithering, the values are in `0.0..1.0`
+    pub fn threshold_map(&self) -> Option<&'static ThresholdMap> {
+        match self {
+            DitherMode::Bayer2 => Some(&BAYER2),
+            DitherMode::Bayer4 => Some(&BAYER4),
+            DitherMode::Bayer8 => Some(&BAYER8),
+            DitherMode::BlueNoise => Some(&BLUE_NOISE),
+            _ => None,
+        }
+    }
+}
+
+/// Square matrix of thresholds tiled over the image
+pub struct ThresholdMap {
+    size: usize,
+    values: Vec<f32>,
+}
+
+impl ThresholdMap {
+    #[inline]
+    pub fn get(&self, x: u32, y: u32) -> f32 {
+        let x = x as usize % self.size;
+        let y = y as usize % self.size;
+        self.values[x + y * self.size]
+    }
+
+    /// Make a map from the ranks of the cells
+    fn from_ranks(size: usize, ranks: &[usize]) -> Self {
+        let n = (size * size) as f32;
+        Self {
+            size,
+            values: ranks.iter().map(|v| (*v as f32 + 0.5) / n).collect(),
+        }
+    }
+
+    fn bayer(size: usize) -> Self {
+        let mut ranks = vec![0usize];
+        let mut n = 1;
+        while n < size {
+            let mut next = vec![0; n * n * 4];
+            for y in 0..n {
+                for x in 0..n {
+                    let v = ranks[x + y * n] * 4;
+                    next[x + y * n * 2] = v;
+                    next[x + n + y * n * 2] = v + 2;
+                    next[x + (y + n) * n * 2] = v + 3;
+                    next[x + n + (y + n) * n * 2] = v + 1;
+                }
+            }
+            ranks = next;
+            n *= 2;
+        }
+        Self::from_ranks(size, &ranks)
+    }
+
+    /// Blue noise by the void-and-cluster method
+    fn blue_noise(size: usize) -> Self {
+        const SIGMA: f64 = 1.5;
+        let len = size * size;
+
+        // Toroidal Gaussian energy of a pixel at each offset
+        let mut lut = vec![0.0f64; len];
+        for y in 0..size {
+            for x in 0..size {
+                let dx = x.min(size - x) as f64;
+                let dy = y.min(size - y) as f64;
+                lut[x + y * size] = (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp();
+            }
+        }
+        let mut pattern = vec![false; len];
+        let mut energy = vec![0.0f64; len];
+        let update = |energy: &mut [f64], index: usize, sign: f64| {
+            let (px, py) = (index % size, index / size);
+            for y in 0..size {
+                for x in 0..size {
+                    let dx = (x + size - px) % size;
+                    let dy = (y + size - py) % size;
+                    energy[x + y * size] += lut[dx + dy * size] * sign;
+                }
+            }
+        };
+        let tightest_cluster = |pattern: &[bool], energy: &[f64]| {
+            (0..len)
+                .filter(|i| pattern[*i])
+                .max_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+                .unwrap()
+        };
+        let largest_void = |pattern: &[bool], energy: &[f64]| {
+            (0..len)
+                .filter(|i| !pattern[*i])
+                .min_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+                .unwrap()
+        };
+
+        // Initial pattern from a fixed pseudo random sequence
+        let ones = len / 10;
+        let mut seed = 0x2545_f491u32;
+        let mut count = 0;
+        while count < ones {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let index = seed as usize % len;
+            if !pattern[index] {
+                pattern[index] = true;
+                update(&mut energy, index, 1.0);
+                count += 1;
+            }
+        }
+
+        // Move the pixels from the clusters into the voids until the pattern is stable
+        loop {
+            let cluster = tightest_cluster(&pattern, &energy);
+            pattern[cluster] = false;
+            update(&mut energy, cluster, -1.0);
+            let void = largest_void(&pattern, &energy);
+            pattern[void] = true;
+            update(&mut energy, void, 1.0);
+            if void == cluster {
+                break;
+            }
+        }
+
+        let mut ranks = vec![0; len];
+        {
+            let mut pattern = pattern.clone();
+            let mut energy = energy.clone();
+            for rank in (0..ones).rev() {
+                let cluster = tightest_cluster(&pattern, &energy);
+                pattern[cluster] = false;
+                update(&mut energy, cluster, -1.0);
+                ranks[cluster] = rank;
+            }
+        }
+        for rank in ones..len {
+            let void = largest_void(&pattern, &energy);
+            pattern[void] = true;
+            update(&mut energy, void, 1.0);
+            ranks[void] = rank;
+        }
+
+        Self::from_ranks(size, &ranks)
+    }
+}
+
+static BAYER2: LazyLock<ThresholdMap> = LazyLock::new(|| ThresholdMap::bayer(2));
+static BAYER4: LazyLock<ThresholdMap> = LazyLock::new(|| ThresholdMap::bayer(4));
+static BAYER8: LazyLock<ThresholdMap> = LazyLock::new(|| ThresholdMap::bayer(8));
+static BLUE_NOISE: LazyLock<ThresholdMap> = LazyLock::new(|| ThresholdMap::blue_noise(64));
+
+/// Approximate distance between the colors of a palette, which scales the ordered dithering
+#[inline]
+pub fn palette_spread(colors: usize) -> f64 {
+    255.0 / (colors.max(1) as f64).cbrt()
+}
+
+/// Quantize the colors of the buffer with dithering
+///
+/// `quantize` maps a color to the nearest available color.
+/// `spread` is the distance between the available levels of each channel, which scales the ordered dithering.
+/// The alpha channel is dithered only if `dither_alpha` is specified.
+/// If `serpentine` is specified, the error diffusion scans odd lines from right to left.
+pub fn dither_buffer<F>(
+    info: &ImageInfo,
+    ib: &mut [u8],
+    mode: DitherMode,
+    serpentine: bool,
+    dither_alpha: bool,
+    spread: [f64; 4],
+    mut quantize: F,
+) where
+    F: FnMut([u8; 4]) -> [u8; 4],
+{
+    let channels = if dither_alpha { 4 } else { 3 };
+    if let Some(kernel) = mode.diffusion_kernel() {
+        diffuse_errors(info, ib, kernel, serpentine, channels, quantize);
+    } else if let Some(map) = mode.threshold_map() {
+        for y in 0..info.height {
+            for x in 0..info.width {
+                let pixel = info.get_pixel(x, y, ib);
+                let threshold = map.get(x, y) as f64 - 0.5;
+                let mut biased = pixel;
+                for ch in 0..channels {
+                    biased[ch] = (pixel[ch] as f64 + threshold * spread[ch])
+                        .round()
+                        .clamp(0.0, 255.0) as u8;
+                }
+                info.set_pixel(x, y, ib, quantize(biased));
+            }
+        }
+    } else {
+        for pixel in ib.chunks_exact_mut(4) {
+            let new_pixel = quantize(pixel.try_into().unwrap());
+            pixel.copy_from_slice(&new_pixel);
+        }
+    }
+}
+
+/// Error diffusion
+///
+/// The errors are kept in `f32` for only as many lines as the kernel covers,
+/// so the shares add up to the whole error without truncation or overflow.
+fn diffuse_errors<F>(
+    info: &ImageInfo,
+    ib: &mut [u8],
+    kernel: DiffusionKernel,
+    serpentine: bool,
+    channels: usize,
+    mut quantize: F,
+) where
+    F: FnMut([u8; 4]) -> [u8; 4],
+{
+    let width = info.width as usize;
+    // Margins on both sides absorb the errors that go outside of the image
+    let margin = kernel
+        .weights
+        .iter()
+        .map(|v| v.0.unsigned_abs())
+        .max()
+        .unwrap_or(0);
+    let stride = width + margin * 2;
+    let rows = kernel.weights.iter().map(|v| v.1).max().unwrap_or(0) + 1;
+    let mut errors = vec![[0.0f32; 4]; stride * rows];
+    let shares = kernel
+        .weights
+        .iter()
+        .map(|&(dx, dy, weight)| (dx, dy, weight as f32 / kernel.divisor as f32))
+        .collect::<Vec<_>>();
+
+    for y in 0..info.height {
+        let row = y as usize % rows;
+        let reverse = serpentine && y % 2 == 1;
+        for i in 0..width {
+            let x = if reverse { width - 1 - i } else { i };
+            let pixel = info.get_pixel(x as u32, y, ib);
+            let error = errors[row * stride + margin + x];
+
+            // The error beyond the range of the channel cannot be represented and is dropped
+            let mut exact = [0.0f32; 4];
+            let mut value = pixel;
+            for ch in 0..channels {
+                exact[ch] = (pixel[ch] as f32 + error[ch]).clamp(0.0, 255.0);
+                value[ch] = exact[ch].round() as u8;
+            }
+            let new_pixel = quantize(value);
+            info.set_pixel(x as u32, y, ib, new_pixel);
+
+            let mut e = [0.0f32; 4];
+            for ch in 0..channels {
+                e[ch] = exact[ch] - new_pixel[ch] as f32;
+            }
+            if e == [0.0; 4] {
+                continue;
+            }
+            for &(dx, dy, share) in shares.iter() {
+                let dx = if reverse { -dx } else { dx };
+                let index =
+                    ((y as usize + dy) % rows) * stride + (margin + x).wrapping_add_signed(dx);
+                let target = &mut errors[index];
+                for ch in 0..channels {
+                    target[ch] += e[ch] * share;
+                }
+            }
+        }
+        // This line is reused for the line after the ones the kernel covers
+        errors[row * stride..(row + 1) * stride].fill([0.0; 4]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Transparency, posterize_buffer};
+
+    const SIZE: u32 = 128;
+
+    /// Grays near both ends also cover the clamping of the accumulated error
+    const GRAYS: [u8; 7] = [1, 10, 64, 128, 200, 250, 254];
+
+    /// Maximum difference of the mean for the kernels that diffuse the whole error,
+    /// only the error pushed out of the edges of the image is lost
+    const TOLERANCE: f64 = 1.5;
+
+    /// Atkinson diffuses only 6/8 of the error, so the mean moves toward the nearer level by design
+    const ATKINSON_TOLERANCE: f64 = 20.0;
+
+    const KERNELS: [DitherMode; 8] = [
+        DitherMode::FloydSteinberg,
+        DitherMode::Atkinson,
+        DitherMode::JarvisJudiceNinke,
+        DitherMode::Stucki,
+        DitherMode::Burkes,
+        DitherMode::Sierra,
+        DitherMode::TwoRowSierra,
+        DitherMode::SierraLite,
+    ];
+
+    fn dither_flat(pixel: [u8; 4], mode: DitherMode, serpentine: bool, levels: [u8; 4]) -> Vec<u8> {
+        let info = ImageInfo::new(SIZE, SIZE, Transparency::Translucent);
+        let mut ib = pixel.repeat(info.number_of_pixels());
+        assert!(posterize_buffer(&info, &mut ib, mode, serpentine, levels));
+        ib
+    }
+
+    fn mean(ib: &[u8], channel: usize) -> f64 {
+        ib.chunks_exact(4).map(|v| v[channel] as f64).sum::<f64>() / (ib.len() / 4) as f64
+    }
+
+    #[test]
+    fn error_diffusion_keeps_mean() {
+        for mode in KERNELS {
+            let tolerance = if mode == DitherMode::Atkinson {
+                ATKINSON_TOLERANCE
+            } else {
+                TOLERANCE
+            };
+            for gray in GRAYS {
+                for serpentine in [false, true] {
+                    let ib = dither_flat([gray, gray, gray, 255], mode, serpentine, [2, 2, 2, 0]);
+                    assert!(
+                        ib.chunks_exact(4)
+                            .all(|v| matches!(v[0], 0 | 255) && v[3] == 255)
+                    );
+                    let diff = mean(&ib, 0) - gray as f64;
+                    assert!(
+                        diff.abs() <= tolerance,
+                        "{mode:?} gray {gray} serpentine {serpentine}: {diff}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn error_diffusion_keeps_mean_of_alpha() {
+        for mode in KERNELS.into_iter().filter(|v| *v != DitherMode::Atkinson) {
+            let ib = dither_flat([128, 128, 128, 100], mode, true, [2, 2, 2, 2]);
+            assert!(ib.chunks_exact(4).all(|v| matches!(v[3], 0 | 255)));
+            let diff = mean(&ib, 3) - 100.0;
+            assert!(diff.abs() <= TOLERANCE, "{mode:?}: {diff}");
+        }
+    }
+}
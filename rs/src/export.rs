@@ -0,0 +1,286 @@
+//! Export the image as source code that can be embedded in firmware
+
+use crate::{ImageInfo, dither::DitherMode, image_buffer, image_info, luminance, posterize_buffer};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SourceLanguage {
+    C,
+    Rust,
+}
+
+#[wasm_bindgen]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PixelFormat {
+    /// 32bit per pixel, `0xRRGGBBAA`
+    Rgba8888,
+    /// 3 bytes per pixel, R, G, B
+    Rgb888,
+    /// 16bit per pixel, `RRRRRGGGGGGBBBBB`
+    Rgb565,
+    /// 16bit per pixel, `0RRRRRGGGGGBBBBB`
+    Rgb555,
+    /// 8bit per pixel, `RRRGGGBB`
+    Rgb332,
+    /// 8bit per pixel, luminance
+    Gray8,
+    /// 1bit per pixel, MSB first, each line is padded to a byte boundary, 1 is white
+    Mono1,
+}
+
+impl PixelFormat {
+    /// Bit width of an element of the output array
+    #[inline]
+    pub const fn element_bits(&self) -> u32 {
+        match self {
+            PixelFormat::Rgba8888 => 32,
+            PixelFormat::Rgb565 | PixelFormat::Rgb555 => 16,
+            PixelFormat::Rgb888 | PixelFormat::Rgb332 | PixelFormat::Gray8 | PixelFormat::Mono1 => {
+                8
+            }
+        }
+    }
+
+    #[inline]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            PixelFormat::Rgba8888 => "RGBA8888",
+            PixelFormat::Rgb888 => "RGB888",
+            PixelFormat::Rgb565 => "RGB565",
+            PixelFormat::Rgb555 => "RGB555",
+            PixelFormat::Rgb332 => "RGB332",
+            PixelFormat::Gray8 => "Grayscale 8bit",
+            PixelFormat::Mono1 => "Monochrome 1bit",
+        }
+    }
+}
+
+/// Export the current image as a C header or Rust source
+///
+/// Colors are reduced with `posterize` before packing, so `dither` applies to the formats with fewer levels.
+/// If `rle` is specified, the array consists of pairs of run length and value.
+#[wasm_bindgen]
+pub fn export_source(
+    name: &str,
+    language: SourceLanguage,
+    format: PixelFormat,
+    dither: DitherMode,
+    rle: bool,
+) -> Option<String> {
+    let info = image_info();
+    if info.number_of_pixels() == 0 {
+        return None;
+    }
+
+    // Color reduction must not affect the current image
+    let mut ib = image_buffer().clone();
+    let data = pack_pixels(info, &mut ib, format, dither)?;
+    let data = if rle {
+        encode_rle(&data, format.element_bits())
+    } else {
+        data
+    };
+
+    Some(make_source(
+        &identifier(name),
+        language,
+        format,
+        info,
+        rle,
+        &data,
+    ))
+}
+
+/// Convert the buffer into an array of elements of the specified pixel format
+pub fn pack_pixels(
+    info: &ImageInfo,
+    ib: &mut [u8],
+    format: PixelFormat,
+    dither: DitherMode,
+) -> Option<Vec<u32>> {
+    let mut vec = Vec::new();
+    match format {
+        PixelFormat::Rgba8888 => {
+            vec.reserve(info.number_of_pixels());
+            for rgba in ib.chunks_exact(4) {
+                vec.push(u32::from_be_bytes(rgba.try_into().unwrap()));
+            }
+        }
+        PixelFormat::Rgb888 => {
+            vec.reserve(info.number_of_pixels() * 3);
+            for rgba in ib.chunks_exact(4) {
+                vec.push(rgba[0] as u32);
+                vec.push(rgba[1] as u32);
+                vec.push(rgba[2] as u32);
+            }
+        }
+        PixelFormat::Rgb565 => {
+            if !posterize_buffer(info, ib, dither, false, [32, 64, 32, 0]) {
+                return None;
+            }
+            vec.reserve(info.number_of_pixels());
+            for rgba in ib.chunks_exact(4) {
+                vec.push(
+                    (level(rgba[0], 31) << 11) | (level(rgba[1], 63) << 5) | level(rgba[2], 31),
+                );
+            }
+        }
+        PixelFormat::Rgb555 => {
+            if !posterize_buffer(info, ib, dither, false, [32, 32, 32, 0]) {
+                return None;
+            }
+            vec.reserve(info.number_of_pixels());
+            for rgba in ib.chunks_exact(4) {
+                vec.push(
+                    (level(rgba[0], 31) << 10) | (level(rgba[1], 31) << 5) | level(rgba[2], 31),
+                );
+            }
+        }
+        PixelFormat::Rgb332 => {
+            if !posterize_buffer(info, ib, dither, false, [8, 8, 4, 0]) {
+                return None;
+            }
+            vec.reserve(info.number_of_pixels());
+            for rgba in ib.chunks_exact(4) {
+                vec.push((level(rgba[0], 7) << 5) | (level(rgba[1], 7) << 2) | level(rgba[2], 3));
+            }
+        }
+        PixelFormat::Gray8 => {
+            vec.reserve(info.number_of_pixels());
+            for rgba in ib.chunks_exact(4) {
+                vec.push(luminance(rgba) as u32);
+            }
+        }
+        PixelFormat::Mono1 => {
+            for pixel in ib.chunks_exact_mut(4) {
+                let gray = luminance(pixel);
+                pixel[0] = gray;
+                pixel[1] = gray;
+                pixel[2] = gray;
+            }
+            if !posterize_buffer(info, ib, dither, false, [2, 2, 2, 0]) {
+                return None;
+            }
+            let stride = info.width as usize * 4;
+            vec.reserve(info.width.div_ceil(8) as usize * info.height as usize);
+            for line in ib.chunks_exact(stride) {
+                for pixels in line.chunks(4 * 8) {
+                    let mut acc = 0;
+                    for (index, rgba) in pixels.chunks_exact(4).enumerate() {
+                        if rgba[0] >= 0x80 {
+                            acc |= 0x80 >> index;
+                        }
+                    }
+                    vec.push(acc);
+                }
+            }
+        }
+    }
+    Some(vec)
+}
+
+/// Scale an 8bit channel value to `0..=max`
+#[inline]
+fn level(value: u8, max: u32) -> u32 {
+    (value as u32 * max + 127) / 255
+}
+
+/// Encode an array into pairs of run length and value
+pub fn encode_rle(data: &[u32], element_bits: u32) -> Vec<u32> {
+    let max_run = u32::MAX >> (32 - element_bits);
+    let mut vec = Vec::new();
+    let mut iter = data.iter();
+    let Some(&first) = iter.next() else {
+        return vec;
+    };
+    let mut current = first;
+    let mut count = 1;
+    for &value in iter {
+        if value == current && count < max_run {
+            count += 1;
+        } else {
+            vec.push(count);
+            vec.push(current);
+            current = value;
+            count = 1;
+        }
+    }
+    vec.push(count);
+    vec.push(current);
+    vec
+}
+
+/// Make a valid upper case identifier from the specified name
+fn identifier(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    for ch in name.chars() {
+        if ch.is_ascii_alphanumeric() {
+            result.push(ch.to_ascii_uppercase());
+        } else if !result.ends_with('_') {
+            result.push('_');
+        }
+    }
+    let result = result.trim_matches('_');
+    if result.is_empty() {
+        "IMAGE".to_owned()
+    } else if result.starts_with(|ch: char| ch.is_ascii_digit()) {
+        format!("_{result}")
+    } else {
+        result.to_owned()
+    }
+}
+
+fn make_source(
+    name: &str,
+    language: SourceLanguage,
+    format: PixelFormat,
+    info: &ImageInfo,
+    rle: bool,
+    data: &[u32],
+) -> String {
+    let bits = format.element_bits();
+    let digits = bits as usize / 4;
+    let items_per_line = 96 / (digits + 4);
+
+    let mut s = String::new();
+    let _ = writeln!(
+        s,
+        "// {name}: {} x {}, {}{}",
+        info.width,
+        info.height,
+        format.as_str(),
+        if rle { ", RLE (run length, value)" } else { "" },
+    );
+    match language {
+        SourceLanguage::C => {
+            let _ = writeln!(s, "#pragma once");
+            let _ = writeln!(s, "#include <stdint.h>");
+            let _ = writeln!(s);
+            let _ = writeln!(s, "#define {name}_WIDTH {}", info.width);
+            let _ = writeln!(s, "#define {name}_HEIGHT {}", info.height);
+            let _ = writeln!(s);
+            let _ = writeln!(s, "static const uint{bits}_t {name}[{}] = {{", data.len());
+        }
+        SourceLanguage::Rust => {
+            let _ = writeln!(s, "pub const {name}_WIDTH: usize = {};", info.width);
+            let _ = writeln!(s, "pub const {name}_HEIGHT: usize = {};", info.height);
+            let _ = writeln!(s, "pub const {name}: [u{bits}; {}] = [", data.len());
+        }
+    }
+    for line in data.chunks(items_per_line) {
+        s.push_str("   ");
+        for value in line {
+            let _ = write!(s, " 0x{value:0digits$x},");
+        }
+        s.push('\n');
+    }
+    match language {
+        SourceLanguage::C => s.push_str("};\n"),
+        SourceLanguage::Rust => s.push_str("];\n"),
+    }
+    s
+}
@@ -0,0 +1,635 @@
+//! Spatial filters
+//!
+//! Colors are convolved in premultiplied alpha so that transparent pixels do not bleed into the image,
+//! and in linear light if enabled.
+
+use crate::{ImageInfo, MAGIC_NUMBER, gamma::Transfer, image_buffer, image_info};
+use alloc::vec::Vec;
+use wasm_bindgen::prelude::*;
+
+/// Gaussian blurs with a larger sigma are approximated by three box blurs
+const MAX_GAUSSIAN_KERNEL_SIGMA: f64 = 8.0;
+
+/// How the pixels outside of the image are sampled
+#[wasm_bindgen]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeMode {
+    /// Repeat the pixels on the edges
+    Clamp,
+    /// Tile the image
+    Wrap,
+    /// Reflect the image at the edges
+    Mirror,
+    /// Transparent black
+    Transparent,
+}
+
+impl EdgeMode {
+    /// Index of the pixel to sample, `None` means transparent black
+    #[inline]
+    pub fn index(&self, i: isize, len: usize) -> Option<usize> {
+        let len = len as isize;
+        if (0..len).contains(&i) {
+            return Some(i as usize);
+        }
+        match self {
+            EdgeMode::Clamp => Some(i.clamp(0, len - 1) as usize),
+            EdgeMode::Wrap => Some(i.rem_euclid(len) as usize),
+            EdgeMode::Mirror => {
+                let i = i.rem_euclid(len * 2);
+                Some(if i < len { i } else { len * 2 - 1 - i } as usize)
+            }
+            EdgeMode::Transparent => None,
+        }
+    }
+}
+
+#[wasm_bindgen]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeOperator {
+    Sobel,
+    Prewitt,
+    Laplacian,
+}
+
+/// Premultiplied values of the pixels for calculation
+type Planes = Vec<[f32; 4]>;
+
+/// Convolve the image with an arbitrary kernel
+///
+/// `kernel` has `width * height` weights in the row-major order, and both sizes must be odd.
+/// The sum is divided by `divisor` (the sum of the weights if `0.0`) and `bias` (`0.0` to `1.0`) is added.
+/// If `preserve_alpha` is specified, only the colors are convolved and the alpha channel is kept.
+#[wasm_bindgen]
+pub fn convolve(
+    kernel: &[f64],
+    width: u32,
+    height: u32,
+    divisor: f64,
+    bias: f64,
+    edge: EdgeMode,
+    preserve_alpha: bool,
+) -> bool {
+    let (kw, kh) = (width as usize, height as usize);
+    if kw % 2 == 0
+        || kh % 2 == 0
+        || kernel.len() != kw * kh
+        || !divisor.is_finite()
+        || !bias.is_finite()
+    {
+        return false;
+    }
+    let divisor = if divisor != 0.0 {
+        divisor
+    } else {
+        let sum = kernel.iter().sum::<f64>();
+        if sum != 0.0 { sum } else { 1.0 }
+    };
+    let kernel = kernel
+        .iter()
+        .map(|v| (v / divisor) as f32)
+        .collect::<Vec<_>>();
+
+    let info = image_info();
+    let ib = image_buffer();
+    if preserve_alpha {
+        let bias = (bias * 255.0) as f32;
+        let Some(ob) = convolve_color_buffer(info, ib, &kernel, kw, kh, edge, |v| v + bias) else {
+            return false;
+        };
+        ib.copy_from_slice(&ob);
+    } else {
+        let transfer = Transfer::current();
+        let Some(planes) = decode_planes(ib, transfer) else {
+            return false;
+        };
+        let Some(mut result) = convolve_planes(info, &planes, &kernel, kw, kh, edge) else {
+            return false;
+        };
+        let bias = (bias * 255.0) as f32;
+        for value in result.iter_mut() {
+            for ch in 0..3 {
+                value[ch] += bias * value[3] / 255.0;
+            }
+        }
+        encode_planes(&result, ib, transfer);
+        crate::update_transparency(info, ib);
+    }
+    info.is_grayscale = false;
+    true
+}
+
+/// Blur with a Gaussian of the standard deviation `sigma`
+#[wasm_bindgen]
+pub fn gaussian_blur(sigma: f64, edge: EdgeMode) -> bool {
+    if !(sigma.is_finite() && sigma >= 0.0) {
+        return false;
+    }
+    let info = image_info();
+    let ib = image_buffer();
+    let transfer = Transfer::current();
+    let Some(planes) = decode_planes(ib, transfer) else {
+        return false;
+    };
+    let Some(result) = gaussian_planes(info, planes, sigma, edge) else {
+        return false;
+    };
+    encode_planes(&result, ib, transfer);
+    crate::update_transparency(info, ib);
+    true
+}
+
+/// Blur with the average of the square of `radius * 2 + 1` pixels on a side
+#[wasm_bindgen]
+pub fn box_blur(radius: u32, edge: EdgeMode) -> bool {
+    let info = image_info();
+    let ib = image_buffer();
+    let transfer = Transfer::current();
+    let Some(planes) = decode_planes(ib, transfer) else {
+        return false;
+    };
+    let Some(result) = box_planes(info, planes, &[radius as usize], edge) else {
+        return false;
+    };
+    encode_planes(&result, ib, transfer);
+    crate::update_transparency(info, ib);
+    true
+}
+
+/// Sharpen with a 3x3 kernel, `amount` is the strength
+#[wasm_bindgen]
+pub fn sharpen(amount: f64, edge: EdgeMode) -> bool {
+    if !(amount.is_finite() && amount >= 0.0) {
+        return false;
+    }
+    let a = -amount;
+    #[rustfmt::skip]
+    let kernel = [
+        0.0, a, 0.0,
+        a, 1.0 + amount * 4.0, a,
+        0.0, a, 0.0,
+    ];
+    convolve(&kernel, 3, 3, 1.0, 0.0, edge, false)
+}
+
+/// Sharpen by adding the difference from the Gaussian blurred image
+///
+/// `amount` is the ratio of the difference added, `radius` is the sigma of the blur,
+/// and the channels that differ by less than `threshold` are left unchanged.
+#[wasm_bindgen]
+pub fn unsharp_mask(amount: f64, radius: f64, threshold: u8) -> bool {
+    if !(amount.is_finite() && radius.is_finite() && radius >= 0.0) {
+        return false;
+    }
+    let info = image_info();
+    let ib = image_buffer();
+    let transfer = Transfer::current();
+    let Some(planes) = decode_planes(ib, transfer) else {
+        return false;
+    };
+    let Some(blurred) = gaussian_planes(info, planes, radius, EdgeMode::Clamp) else {
+        return false;
+    };
+
+    for (pixel, value) in ib.chunks_exact_mut(MAGIC_NUMBER).zip(blurred) {
+        let value = [
+            value[0] as f64,
+            value[1] as f64,
+            value[2] as f64,
+            value[3] as f64,
+        ];
+        let blurred = transfer.encode_pixel(value);
+        for ch in 0..3 {
+            let diff = pixel[ch] as f64 - blurred[ch] as f64;
+            if diff.abs() >= threshold as f64 {
+                pixel[ch] = (pixel[ch] as f64 + diff * amount).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+    true
+}
+
+/// Emboss the image, the result is gray where the image is flat
+#[wasm_bindgen]
+pub fn emboss(strength: f64) -> bool {
+    if !strength.is_finite() {
+        return false;
+    }
+    let s = strength as f32;
+    #[rustfmt::skip]
+    let kernel = [
+        -2.0 * s, -s, 0.0,
+        -s, 0.0, s,
+        0.0, s, 2.0 * s,
+    ];
+    let info = image_info();
+    let ib = image_buffer();
+    let Some(ob) = convolve_color_buffer(info, ib, &kernel, 3, 3, EdgeMode::Clamp, |v| v + 128.0)
+    else {
+        return false;
+    };
+    ib.copy_from_slice(&ob);
+    info.is_grayscale = false;
+    true
+}
+
+/// Detect the edges, the result is bright at the edges and black elsewhere
+#[wasm_bindgen]
+pub fn edge_detect(operator: EdgeOperator) -> bool {
+    #[rustfmt::skip]
+    const SOBEL: [f32; 9] = [
+        -1.0, 0.0, 1.0,
+        -2.0, 0.0, 2.0,
+        -1.0, 0.0, 1.0,
+    ];
+    #[rustfmt::skip]
+    const PREWITT: [f32; 9] = [
+        -1.0, 0.0, 1.0,
+        -1.0, 0.0, 1.0,
+        -1.0, 0.0, 1.0,
+    ];
+    #[rustfmt::skip]
+    const LAPLACIAN: [f32; 9] = [
+        -1.0, -1.0, -1.0,
+        -1.0, 8.0, -1.0,
+        -1.0, -1.0, -1.0,
+    ];
+
+    let info = image_info();
+    let ib = image_buffer();
+    let ob = match operator {
+        EdgeOperator::Sobel => gradient_buffer(info, ib, &SOBEL),
+        EdgeOperator::Prewitt => gradient_buffer(info, ib, &PREWITT),
+        EdgeOperator::Laplacian => {
+            // The absolute value of the second derivative
+            convolve_color_buffer(info, ib, &LAPLACIAN, 3, 3, EdgeMode::Clamp, f32::abs)
+        }
+    };
+    let Some(ob) = ob else {
+        return false;
+    };
+    ib.copy_from_slice(&ob);
+    info.is_grayscale = false;
+    true
+}
+
+/// Allocate planes of the specified number of pixels
+fn alloc_planes(len: usize) -> Option<Planes> {
+    let mut planes = Vec::new();
+    planes.try_reserve(len).ok()?;
+    Some(planes)
+}
+
+/// Convert the buffer into premultiplied values
+pub fn decode_planes(ib: &[u8], transfer: Transfer) -> Option<Planes> {
+    let mut planes = alloc_planes(ib.len() / MAGIC_NUMBER)?;
+    let table: [f32; 256] = core::array::from_fn(|i| transfer.decode(i as u8, 0) as f32);
+    for pixel in ib.chunks_exact(MAGIC_NUMBER) {
+        let alpha = pixel[3] as f32;
+        let factor = alpha / 255.0;
+        planes.push([
+            table[pixel[0] as usize] * factor,
+            table[pixel[1] as usize] * factor,
+            table[pixel[2] as usize] * factor,
+            alpha,
+        ]);
+    }
+    Some(planes)
+}
+
+/// Convert premultiplied values back into the buffer
+pub fn encode_planes(planes: &[[f32; 4]], ib: &mut [u8], transfer: Transfer) {
+    for (pixel, value) in ib.chunks_exact_mut(MAGIC_NUMBER).zip(planes) {
+        let value = [
+            value[0] as f64,
+            value[1] as f64,
+            value[2] as f64,
+            value[3] as f64,
+        ];
+        pixel.copy_from_slice(&transfer.encode_pixel(value));
+    }
+}
+
+/// Convolve the planes with a 2D kernel
+pub fn convolve_planes(
+    info: &ImageInfo,
+    planes: &[[f32; 4]],
+    kernel: &[f32],
+    kw: usize,
+    kh: usize,
+    edge: EdgeMode,
+) -> Option<Planes> {
+    let width = info.width as usize;
+    let height = info.height as usize;
+    let (rx, ry) = ((kw / 2) as isize, (kh / 2) as isize);
+    let mut result = alloc_planes(width * height)?;
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let mut acc = [0.0f32; 4];
+            for (ky, row) in kernel.chunks_exact(kw).enumerate() {
+                let Some(sy) = edge.index(y + ky as isize - ry, height) else {
+                    continue;
+                };
+                for (kx, weight) in row.iter().enumerate() {
+                    let Some(sx) = edge.index(x + kx as isize - rx, width) else {
+                        continue;
+                    };
+                    let value = planes[sx + sy * width];
+                    for ch in 0..4 {
+                        acc[ch] += value[ch] * weight;
+                    }
+                }
+            }
+            result.push(acc);
+        }
+    }
+    Some(result)
+}
+
+/// Convolve only the colors of the buffer, keeping the alpha channel
+///
+/// The colors are premultiplied by the alpha so that the transparent pixels are regarded as black,
+/// and each result is divided by the alpha of the pixel and converted by `map` before it is stored.
+fn convolve_color_buffer(
+    info: &ImageInfo,
+    ib: &[u8],
+    kernel: &[f32],
+    kw: usize,
+    kh: usize,
+    edge: EdgeMode,
+    map: impl Fn(f32) -> f32,
+) -> Option<Vec<u8>> {
+    let planes = premultiplied_planes(ib)?;
+    let result = convolve_planes(info, &planes, kernel, kw, kh, edge)?;
+    let mut ob = ib.to_vec();
+    for (pixel, value) in ob.chunks_exact_mut(MAGIC_NUMBER).zip(result) {
+        let Some(factor) = unpremultiply_factor(pixel[3]) else {
+            pixel[..3].fill(0);
+            continue;
+        };
+        for ch in 0..3 {
+            pixel[ch] = map(value[ch] * factor).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    Some(ob)
+}
+
+/// Magnitude of the gradient of the colors with a horizontal kernel and its transpose
+fn gradient_buffer(info: &ImageInfo, ib: &[u8], kernel_x: &[f32; 9]) -> Option<Vec<u8>> {
+    let kernel_y: [f32; 9] = core::array::from_fn(|i| kernel_x[(i % 3) * 3 + i / 3]);
+    let planes = premultiplied_planes(ib)?;
+    let gx = convolve_planes(info, &planes, kernel_x, 3, 3, EdgeMode::Clamp)?;
+    let gy = convolve_planes(info, &planes, &kernel_y, 3, 3, EdgeMode::Clamp)?;
+    let mut ob = ib.to_vec();
+    for (pixel, (gx, gy)) in ob
+        .chunks_exact_mut(MAGIC_NUMBER)
+        .zip(gx.iter().zip(gy.iter()))
+    {
+        let Some(factor) = unpremultiply_factor(pixel[3]) else {
+            pixel[..3].fill(0);
+            continue;
+        };
+        for ch in 0..3 {
+            pixel[ch] = ((gx[ch] * gx[ch] + gy[ch] * gy[ch]).sqrt() * factor)
+                .round()
+                .clamp(0.0, 255.0) as u8;
+        }
+    }
+    Some(ob)
+}
+
+/// Values of the buffer without the transfer function, with the colors premultiplied by the alpha
+fn premultiplied_planes(ib: &[u8]) -> Option<Planes> {
+    let mut planes = alloc_planes(ib.len() / MAGIC_NUMBER)?;
+    for pixel in ib.chunks_exact(MAGIC_NUMBER) {
+        let alpha = pixel[3] as f32;
+        let factor = alpha / 255.0;
+        planes.push([
+            pixel[0] as f32 * factor,
+            pixel[1] as f32 * factor,
+            pixel[2] as f32 * factor,
+            alpha,
+        ]);
+    }
+    Some(planes)
+}
+
+/// Factor that converts premultiplied colors of the alpha back, `None` if fully transparent
+#[inline]
+fn unpremultiply_factor(alpha: u8) -> Option<f32> {
+    (alpha > 0).then(|| 255.0 / alpha as f32)
+}
+
+/// Separable Gaussian blur of the planes
+pub fn gaussian_planes(
+    info: &ImageInfo,
+    planes: Planes,
+    sigma: f64,
+    edge: EdgeMode,
+) -> Option<Planes> {
+    if sigma <= 0.0 {
+        return Some(planes);
+    }
+    if sigma > MAX_GAUSSIAN_KERNEL_SIGMA {
+        return box_planes(info, planes, &box_radii_for_gaussian(sigma), edge);
+    }
+
+    let radius = (sigma * 3.0).ceil() as isize;
+    let mut kernel = (-radius..=radius)
+        .map(|x| (-(x * x) as f64 / (2.0 * sigma * sigma)).exp() as f32)
+        .collect::<Vec<_>>();
+    let sum = kernel.iter().sum::<f32>();
+    for weight in kernel.iter_mut() {
+        *weight /= sum;
+    }
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    let planes = convolve_lines(&planes, width, height, &kernel, edge)?;
+    let planes = transpose_planes(&planes, width, height)?;
+    let planes = convolve_lines(&planes, height, width, &kernel, edge)?;
+    transpose_planes(&planes, height, width)
+}
+
+/// Box blurs applied successively with each radius
+pub fn box_planes(
+    info: &ImageInfo,
+    planes: Planes,
+    radii: &[usize],
+    edge: EdgeMode,
+) -> Option<Planes> {
+    let (width, height) = (info.width as usize, info.height as usize);
+    let mut planes = planes;
+    for &radius in radii {
+        planes = box_lines(&planes, width, height, radius, edge)?;
+    }
+    let mut planes = transpose_planes(&planes, width, height)?;
+    for &radius in radii {
+        planes = box_lines(&planes, height, width, radius, edge)?;
+    }
+    transpose_planes(&planes, height, width)
+}
+
+/// Radii of three box blurs approximating a Gaussian blur
+fn box_radii_for_gaussian(sigma: f64) -> [usize; 3] {
+    const N: f64 = 3.0;
+    let ideal = (12.0 * sigma * sigma / N + 1.0).sqrt();
+    let mut lower = ideal.floor() as usize;
+    if lower.is_multiple_of(2) {
+        lower -= 1;
+    }
+    let upper = lower + 2;
+    let l = lower as f64;
+    let m = ((12.0 * sigma * sigma - N * l * l - 4.0 * N * l - 3.0 * N) / (-4.0 * l - 4.0)).round()
+        as usize;
+    core::array::from_fn(|i| {
+        if i < m {
+            (lower - 1) / 2
+        } else {
+            (upper - 1) / 2
+        }
+    })
+}
+
+/// Convolve each line horizontally with a 1D kernel centered on the pixel
+fn convolve_lines(
+    planes: &[[f32; 4]],
+    width: usize,
+    height: usize,
+    kernel: &[f32],
+    edge: EdgeMode,
+) -> Option<Planes> {
+    let radius = (kernel.len() / 2) as isize;
+    let mut result = alloc_planes(width * height)?;
+    for line in planes.chunks_exact(width).take(height) {
+        for x in 0..width as isize {
+            let mut acc = [0.0f32; 4];
+            for (k, weight) in kernel.iter().enumerate() {
+                let Some(sx) = edge.index(x + k as isize - radius, width) else {
+                    continue;
+                };
+                for ch in 0..4 {
+                    acc[ch] += line[sx][ch] * weight;
+                }
+            }
+            result.push(acc);
+        }
+    }
+    Some(result)
+}
+
+/// Box blur of each line with running sums, so that the cost does not depend on the radius
+fn box_lines(
+    planes: &[[f32; 4]],
+    width: usize,
+    height: usize,
+    radius: usize,
+    edge: EdgeMode,
+) -> Option<Planes> {
+    let radius = radius as isize;
+    let scale = 1.0 / (radius * 2 + 1) as f64;
+    let mut result = alloc_planes(width * height)?;
+    for line in planes.chunks_exact(width).take(height) {
+        let sample = |i: isize| edge.index(i, width).map(|i| line[i]).unwrap_or_default();
+        let mut acc = [0.0f64; 4];
+        for i in -radius..=radius {
+            let value = sample(i);
+            for ch in 0..4 {
+                acc[ch] += value[ch] as f64;
+            }
+        }
+        for x in 0..width as isize {
+            result.push(core::array::from_fn(|ch| (acc[ch] * scale) as f32));
+            let incoming = sample(x + radius + 1);
+            let outgoing = sample(x - radius);
+            for ch in 0..4 {
+                acc[ch] += incoming[ch] as f64 - outgoing[ch] as f64;
+            }
+        }
+    }
+    Some(result)
+}
+
+fn transpose_planes(planes: &[[f32; 4]], width: usize, height: usize) -> Option<Planes> {
+    let mut result = alloc_planes(width * height)?;
+    for x in 0..width {
+        for y in 0..height {
+            result.push(planes[x + y * width]);
+        }
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Transparency;
+
+    /// Pixels of varying alpha, including fully transparent and opaque ones
+    fn translucent(width: u32, height: u32) -> (ImageInfo, Vec<u8>) {
+        let mut ib = Vec::new();
+        for i in 0..width * height {
+            let alpha = [128, 255, 1, 64, 0, 200][i as usize % 6];
+            ib.extend_from_slice(&[200, 100, (i * 37 % 256) as u8, alpha]);
+        }
+        (ImageInfo::new(width, height, Transparency::Translucent), ib)
+    }
+
+    #[test]
+    fn identity_kernel_keeps_translucent_pixels() {
+        let info = ImageInfo::new(1, 1, Transparency::Translucent);
+        let ib = [200, 100, 50, 128];
+        let ob = convolve_color_buffer(&info, &ib, &[1.0], 1, 1, EdgeMode::Clamp, |v| v).unwrap();
+        assert_eq!(ob, ib);
+
+        let (info, ib) = translucent(7, 5);
+        #[rustfmt::skip]
+        let kernel = [
+            0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0,
+        ];
+        for edge in [
+            EdgeMode::Clamp,
+            EdgeMode::Wrap,
+            EdgeMode::Mirror,
+            EdgeMode::Transparent,
+        ] {
+            let ob = convolve_color_buffer(&info, &ib, &kernel, 3, 3, edge, |v| v).unwrap();
+            for (result, pixel) in ob
+                .chunks_exact(MAGIC_NUMBER)
+                .zip(ib.chunks_exact(MAGIC_NUMBER))
+            {
+                let expected = if pixel[3] == 0 {
+                    [0; 4]
+                } else {
+                    pixel.try_into().unwrap()
+                };
+                assert_eq!(result, expected, "{edge:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn gradient_of_flat_translucent_image_is_black() {
+        let info = ImageInfo::new(5, 4, Transparency::Translucent);
+        let ib = [90, 180, 30, 100].repeat(20);
+        let ob = gradient_buffer(
+            &info,
+            &ib,
+            &[-1.0, 0.0, 1.0, -2.0, 0.0, 2.0, -1.0, 0.0, 1.0],
+        )
+        .unwrap();
+        assert!(ob.chunks_exact(MAGIC_NUMBER).all(|v| v == [0, 0, 0, 100]));
+
+        // Emboss of a flat image is the bias regardless of the alpha
+        let kernel = [-2.0, -1.0, 0.0, -1.0, 0.0, 1.0, 0.0, 1.0, 2.0];
+        let ob = convolve_color_buffer(&info, &ib, &kernel, 3, 3, EdgeMode::Clamp, |v| v + 128.0)
+            .unwrap();
+        assert!(
+            ob.chunks_exact(MAGIC_NUMBER)
+                .all(|v| v == [128, 128, 128, 100])
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Conversion between sRGB and linear light
+//!
+//! When linear light is enabled, resampling and filtering average the colors in linear light instead of the sRGB encoded values.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::sync::LazyLock;
+use wasm_bindgen::prelude::*;
+
+static LINEAR_LIGHT: AtomicBool = AtomicBool::new(false);
+
+/// Number of entries of the table to convert from linear light to sRGB
+const LINEAR_TO_SRGB_SIZE: usize = 4096;
+
+static SRGB_TO_LINEAR: LazyLock<[f32; 256]> = LazyLock::new(|| {
+    let mut table = [0.0; 256];
+    for (i, value) in table.iter_mut().enumerate() {
+        *value = (srgb_to_linear(i as f64 / 255.0) * 255.0) as f32;
+    }
+    table
+});
+
+static LINEAR_TO_SRGB: LazyLock<[u8; LINEAR_TO_SRGB_SIZE]> = LazyLock::new(|| {
+    let mut table = [0; LINEAR_TO_SRGB_SIZE];
+    let max = (LINEAR_TO_SRGB_SIZE - 1) as f64;
+    for (i, value) in table.iter_mut().enumerate() {
+        *value = (linear_to_srgb(i as f64 / max) * 255.0).round() as u8;
+    }
+    table
+});
+
+/// Enable or disable processing in linear light
+#[wasm_bindgen]
+pub fn set_linear_light(value: bool) {
+    LINEAR_LIGHT.store(value, Ordering::Relaxed);
+}
+
+#[wasm_bindgen]
+pub fn linear_light() -> bool {
+    LINEAR_LIGHT.load(Ordering::Relaxed)
+}
+
+#[inline]
+pub fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[inline]
+pub fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Conversion between the stored channel values and the values used for calculation
+///
+/// Both are in the range of `0.0..=255.0`, the alpha channel is never converted.
+/// Whole pixels are converted to premultiplied alpha so that transparent colors do not bleed into the edges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transfer {
+    linear: bool,
+}
+
+impl Transfer {
+    /// The transfer according to the current setting
+    #[inline]
+    pub fn current() -> Self {
+        Self {
+            linear: linear_light(),
+        }
+    }
+
+    #[inline]
+    pub const fn is_linear(&self) -> bool {
+        self.linear
+    }
+
+    #[inline]
+    pub fn decode(&self, value: u8, channel: usize) -> f64 {
+        if self.linear && channel < 3 {
+            SRGB_TO_LINEAR[value as usize] as f64
+        } else {
+            value as f64
+        }
+    }
+
+    /// Convert back to the stored value with rounding and clamping
+    #[inline]
+    pub fn encode(&self, value: f64, channel: usize) -> u8 {
+        if self.linear && channel < 3 {
+            let index = (value * ((LINEAR_TO_SRGB_SIZE - 1) as f64 / 255.0)).round();
+            LINEAR_TO_SRGB[index.clamp(0.0, (LINEAR_TO_SRGB_SIZE - 1) as f64) as usize]
+        } else {
+            value.round().clamp(0.0, 255.0) as u8
+        }
+    }
+
+    /// Convert a pixel into premultiplied values for calculation
+    #[inline]
+    pub fn decode_pixel(&self, pixel: [u8; 4]) -> [f64; 4] {
+        let alpha = pixel[3] as f64;
+        let factor = alpha / 255.0;
+        [
+            self.decode(pixel[0], 0) * factor,
+            self.decode(pixel[1], 1) * factor,
+            self.decode(pixel[2], 2) * factor,
+            alpha,
+        ]
+    }
+
+    /// Convert premultiplied values back into a pixel
+    #[inline]
+    pub fn encode_pixel(&self, value: [f64; 4]) -> [u8; 4] {
+        let alpha = self.encode(value[3], 3);
+        if alpha == 0 {
+            return [0; 4];
+        }
+        // Premultiplied colors cannot exceed the alpha, even if the filter overshoots
+        let max = value[3].clamp(0.0, 255.0);
+        let factor = 255.0 / max;
+        [
+            self.encode(value[0].clamp(0.0, max) * factor, 0),
+            self.encode(value[1].clamp(0.0, max) * factor, 1),
+            self.encode(value[2].clamp(0.0, max) * factor, 2),
+            alpha,
+        ]
+    }
+}
@@ -1,4 +1,4 @@
-#![no_main]
+#![cfg_attr(not(test), no_main)]
 #![deny(unsafe_op_in_unsafe_fn)]
 
 extern crate alloc;
@@ -8,6 +8,7 @@ use core::{
     cell::{RefCell, UnsafeCell},
     ops::DerefMut,
 };
+use dither::DitherMode;
 use mpic;
 use pixel_scale_detector::get_pixel_scale_from_bytes;
 use pngss::DeflateEncoder;
@@ -15,6 +16,25 @@ use rapid_qoi::{Colors, Qoi};
 use wasm_bindgen::{Clamped, prelude::*};
 use web_sys::{CanvasRenderingContext2d, ImageData};
 
+pub mod base64;
+pub mod color;
+pub mod denoise;
+pub mod dither;
+pub mod export;
+pub mod filter;
+pub mod gamma;
+pub mod palette;
+pub mod pixel_art;
+pub mod quantize;
+pub mod resample;
+pub mod stats;
+pub mod terminal;
+pub mod tone;
+pub mod transform;
+
+/// Number of bytes of each pixel
+pub(crate) const MAGIC_NUMBER: usize = 4;
+
 static mut IMAGE_INFO: UnsafeCell<ImageInfo> = UnsafeCell::new(ImageInfo::empty());
 static mut IMAGE_BUFFER: UnsafeCell<Vec<u8>> = UnsafeCell::new(Vec::new());
 
@@ -121,6 +141,68 @@ pub fn image_type_to_string(val: ImageType) -> String {
     }
 }
 
+impl ImageType {
+    #[inline]
+    pub const fn mime_type(&self) -> &'static str {
+        match self {
+            ImageType::Qoi => "image/qoi",
+            ImageType::Mpic => "image/x-mpic",
+            ImageType::Png => "image/png",
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub fn image_type_to_mime_type(val: ImageType) -> String {
+    val.mime_type().to_owned()
+}
+
+/// Decode an image from a `data:` URI
+///
+/// Only the types that `decode` supports (QOI and MPIC) are decoded.
+/// Returns `false` for other image types such as PNG, which are decoded by the browser on the TS side.
+#[wasm_bindgen]
+pub fn decode_data_uri(uri: &str) -> bool {
+    let Some(uri) = uri.trim().strip_prefix("data:") else {
+        return false;
+    };
+    let Some((media_type, data)) = uri.split_once(',') else {
+        return false;
+    };
+    let mime_type = media_type.split(';').next().unwrap_or_default().trim();
+    if mime_type.starts_with("image/")
+        && ![ImageType::Qoi, ImageType::Mpic]
+            .iter()
+            .any(|v| v.mime_type().eq_ignore_ascii_case(mime_type))
+    {
+        return false;
+    }
+    let buffer = if media_type.ends_with(";base64") {
+        base64::decode(data)
+    } else {
+        percent_decode(data)
+    };
+    match buffer {
+        Some(buffer) => decode(&buffer),
+        None => false,
+    }
+}
+
+fn percent_decode(input: &str) -> Option<Vec<u8>> {
+    let mut vec = Vec::with_capacity(input.len());
+    let mut iter = input.bytes();
+    while let Some(ch) = iter.next() {
+        if ch == b'%' {
+            let hi = (iter.next()? as char).to_digit(16)?;
+            let lo = (iter.next()? as char).to_digit(16)?;
+            vec.push((hi * 16 + lo) as u8);
+        } else {
+            vec.push(ch);
+        }
+    }
+    Some(vec)
+}
+
 #[wasm_bindgen]
 pub fn decode(buffer: &[u8]) -> bool {
     match Qoi::decode_alloc(buffer) {
@@ -251,6 +333,17 @@ pub fn encode(image_type: ImageType) -> Option<Vec<u8>> {
     }
 }
 
+/// Encode the image and return it as a `data:` URI
+#[wasm_bindgen]
+pub fn encode_data_uri(image_type: ImageType) -> Option<String> {
+    let data = encode(image_type)?;
+    Some(format!(
+        "data:{};base64,{}",
+        image_type.mime_type(),
+        base64::encode(&data)
+    ))
+}
+
 pub struct CustomDeflateEncoder;
 
 impl DeflateEncoder for CustomDeflateEncoder {
@@ -275,7 +368,6 @@ pub fn crop(x: u32, y: u32, width: u32, height: u32) -> bool {
         return false;
     }
 
-    const MAGIC_NUMBER: usize = 4;
     let mut ob = Vec::new();
     if ob
         .try_reserve(width as usize * height as usize * MAGIC_NUMBER)
@@ -302,26 +394,65 @@ pub fn crop(x: u32, y: u32, width: u32, height: u32) -> bool {
     _set_image_buffer(ob.as_slice(), width, height)
 }
 
-#[non_exhaustive]
 #[wasm_bindgen]
+#[non_exhaustive]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub enum ScaleMode {
-    Nearest,
-    Bilinear,
-    Bicubic,
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// Position of the old image in the new canvas
+    pub fn offset(&self, old_width: u32, old_height: u32, width: u32, height: u32) -> (i64, i64) {
+        let dx = width as i64 - old_width as i64;
+        let dy = height as i64 - old_height as i64;
+        let x = match self {
+            Anchor::TopLeft | Anchor::Left | Anchor::BottomLeft => 0,
+            Anchor::Top | Anchor::Center | Anchor::Bottom => dx.div_euclid(2),
+            Anchor::TopRight | Anchor::Right | Anchor::BottomRight => dx,
+        };
+        let y = match self {
+            Anchor::TopLeft | Anchor::Top | Anchor::TopRight => 0,
+            Anchor::Left | Anchor::Center | Anchor::Right => dy.div_euclid(2),
+            Anchor::BottomLeft | Anchor::Bottom | Anchor::BottomRight => dy,
+        };
+        (x, y)
+    }
 }
 
+/// Change the size of the canvas without resampling
+///
+/// The image is placed at `anchor` and the new area is filled with `fill` (`0xRRGGBBAA`).
 #[wasm_bindgen]
-pub fn scale(width: u32, height: u32, mode: ScaleMode) -> bool {
+pub fn resize_canvas(width: u32, height: u32, anchor: Anchor, fill: u32) -> bool {
     let info = image_info();
-    if info.width == width && info.height == height {
-        return true;
-    }
+    let (x, y) = anchor.offset(info.width, info.height, width, height);
+    let (Ok(x), Ok(y)) = (i32::try_from(x), i32::try_from(y)) else {
+        return false;
+    };
+    resize_canvas_at(width, height, x, y, fill)
+}
+
+/// Change the size of the canvas without resampling
+///
+/// The top left corner of the image is placed at (`x`, `y`) of the new canvas, which can be negative.
+#[wasm_bindgen]
+pub fn resize_canvas_at(width: u32, height: u32, x: i32, y: i32, fill: u32) -> bool {
+    let info = image_info();
+    let x = x as i64;
+    let y = y as i64;
     if width < 1 || height < 1 {
         return false;
     }
 
-    const MAGIC_NUMBER: usize = 4;
     let mut ob = Vec::new();
     if ob
         .try_reserve(width as usize * height as usize * MAGIC_NUMBER)
@@ -329,242 +460,199 @@ pub fn scale(width: u32, height: u32, mode: ScaleMode) -> bool {
     {
         return false;
     }
+    let fill = fill.to_be_bytes();
+    for _ in 0..width as usize * height as usize {
+        ob.extend_from_slice(&fill);
+    }
 
-    let ib = image_buffer();
-    match mode {
-        ScaleMode::Nearest => scale_nn(info, &ib, &mut ob, width, height),
-        ScaleMode::Bilinear => {
-            if info.width > width && info.height > height {
-                scale_reduction(info, &ib, &mut ob, width, height)
-            } else {
-                scale_linear(info, &ib, &mut ob, width, height)
-            }
-        }
-        ScaleMode::Bicubic => {
-            if info.width > width && info.height > height {
-                scale_reduction(info, &ib, &mut ob, width, height)
-            } else {
-                scale_cubic(info, &ib, &mut ob, width, height)
-            }
+    // Intersection of the old image and the new canvas
+    let left = x.max(0);
+    let top = y.max(0);
+    let right = (x + info.width as i64).min(width as i64);
+    let bottom = (y + info.height as i64).min(height as i64);
+    if left < right && top < bottom {
+        let ib = image_buffer();
+        let len = (right - left) as usize * MAGIC_NUMBER;
+        for dy in top..bottom {
+            let sy = (dy - y) as usize;
+            let sx = (left - x) as usize;
+            let src = (sx + sy * info.width as usize) * MAGIC_NUMBER;
+            let dst = (left as usize + dy as usize * width as usize) * MAGIC_NUMBER;
+            ob[dst..dst + len].copy_from_slice(&ib[src..src + len]);
         }
     }
 
     _set_image_buffer(ob.as_slice(), width, height)
 }
 
-/// Resize a image using nearest neighbor interpolation
-pub fn scale_nn(info: &ImageInfo, ib: &[u8], ob: &mut Vec<u8>, width: u32, height: u32) {
-    let sw = info.width as f64;
-    let sh = info.height as f64;
-    let dw = width as f64;
-    let dh = height as f64;
-
-    for y in 0..height {
-        let vy = y as f64 * sh / dh;
-        for x in 0..width {
-            let vx = x as f64 * sw / dw;
-            let new_pixel = info.get_pixel(vx.floor() as u32, vy.floor() as u32, &ib);
-            ob.extend_from_slice(&new_pixel);
-        }
-    }
+#[wasm_bindgen]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TrimMode {
+    /// Trim transparent pixels (alpha value below or equal to the tolerance)
+    Transparent,
+    /// Trim pixels of the color of the corners
+    CornerColor,
 }
 
-#[inline(always)]
-fn scale_main<F>(info: &ImageInfo, ib: &[u8], ob: &mut Vec<u8>, width: u32, height: u32, kernel: F)
-where
-    F: Fn(&ImageInfo, &[u8], f64, f64, f64, f64) -> [u8; 4],
-{
-    let sw = info.width as f64;
-    let sh = info.height as f64;
-    let dw = width as f64 - 1.0;
-    let dh = height as f64 - 1.0;
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
 
-    for y in 0..height {
-        let vy = y as f64 * sh / dh;
-        for x in 0..width {
-            let vx = x as f64 * sw / dw;
-            let new_pixel = kernel(info, ib, vx, vy, sw, sh);
-            ob.extend_from_slice(&new_pixel);
-        }
+/// Crop the borders of the background and returns the remaining rectangle
+///
+/// Returns `None` if the whole image is background.
+#[wasm_bindgen]
+pub fn auto_trim(tolerance: u8, mode: TrimMode) -> Option<Rect> {
+    let info = image_info();
+    let rect = trim_rect(info, image_buffer(), tolerance, mode)?;
+    if rect.width == info.width && rect.height == info.height {
+        return Some(rect);
     }
+    crop(rect.x, rect.y, rect.width, rect.height).then_some(rect)
 }
 
-/// Resize a image using bilinear interpolation
-pub fn scale_linear(info: &ImageInfo, ib: &[u8], ob: &mut Vec<u8>, width: u32, height: u32) {
-    scale_main(info, ib, ob, width, height, |info, ib, vx, vy, sw, sh| {
-        let vx = (vx - 0.5).max(0.0);
-        let vy = (vy - 0.5).max(0.0);
+/// Bounding box of the pixels that are not background
+pub fn trim_rect(info: &ImageInfo, ib: &[u8], tolerance: u8, mode: TrimMode) -> Option<Rect> {
+    let width = info.width as usize;
+    let height = info.height as usize;
+    if width < 1 || height < 1 {
+        return None;
+    }
 
-        let lx = vx.floor();
-        let ly = vy.floor();
-        let x_frac = vx - lx;
-        let y_frac = vy - ly;
+    let similar = |lhs: &[u8], rhs: &[u8]| {
+        (lhs[3] == 0 && rhs[3] == 0)
+            || lhs
+                .iter()
+                .zip(rhs)
+                .all(|(l, r)| l.abs_diff(*r) <= tolerance)
+    };
+    let background = match mode {
+        TrimMode::Transparent => None,
+        TrimMode::CornerColor => {
+            // The color shared by the most corners
+            let corners = [
+                info.get_pixel(0, 0, ib),
+                info.get_pixel(info.width - 1, 0, ib),
+                info.get_pixel(0, info.height - 1, ib),
+                info.get_pixel(info.width - 1, info.height - 1, ib),
+            ];
+            corners
+                .iter()
+                .max_by_key(|lhs| corners.iter().filter(|rhs| similar(*lhs, *rhs)).count())
+                .copied()
+        }
+    };
+    let is_background = |pixel: &[u8]| match background {
+        Some(background) => similar(pixel, &background),
+        None => pixel[3] <= tolerance,
+    };
+    let has_content = |line: &[u8]| line.chunks_exact(MAGIC_NUMBER).any(|v| !is_background(v));
+
+    let stride = width * MAGIC_NUMBER;
+    let lines = ib.chunks_exact(stride).take(height);
+    let top = lines.clone().position(has_content)?;
+    let bottom = height - lines.rev().position(has_content)?;
+
+    let mut left = width;
+    let mut right = 0;
+    for line in ib.chunks_exact(stride).take(bottom).skip(top) {
+        let pixels = line.chunks_exact(MAGIC_NUMBER);
+        if let Some(x) = pixels.clone().position(|v| !is_background(v)) {
+            left = left.min(x);
+            right = right.max(width - pixels.rev().position(|v| !is_background(v))?);
+        }
+    }
 
-        let hx = (lx + 1.0).floor().min(sw - 1.0);
-        let hy = (ly + 1.0).floor().min(sh - 1.0);
+    Some(Rect {
+        x: left as u32,
+        y: top as u32,
+        width: (right - left) as u32,
+        height: (bottom - top) as u32,
+    })
+}
 
-        let vll = info.get_pixel(lx as u32, ly as u32, ib);
-        let vlh = info.get_pixel(lx as u32, hy as u32, ib);
-        let vhl = info.get_pixel(hx as u32, ly as u32, ib);
-        let vhh = info.get_pixel(hx as u32, hy as u32, ib);
+#[non_exhaustive]
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ScaleMode {
+    Nearest,
+    Bilinear,
+    Bicubic,
+    Lanczos2,
+    Lanczos3,
+    Mitchell,
+    CatmullRom,
+    Hermite,
+    Gaussian,
+}
 
-        let mut result = [0u8; 4];
-        for i in 0..4 {
-            let a = vll[i] as f64;
-            let b = vhl[i] as f64;
-            let c = vlh[i] as f64;
-            let d = vhh[i] as f64;
+#[wasm_bindgen]
+pub fn scale(width: u32, height: u32, mode: ScaleMode) -> bool {
+    scale_xy(width, height, mode, mode)
+}
 
-            let q = a * (1.0 - x_frac) * (1.0 - y_frac)
-                + b * (x_frac) * (1.0 - y_frac)
-                + c * (y_frac) * (1.0 - x_frac)
-                + d * (x_frac * y_frac);
+/// Resize the image with separate modes for horizontal and vertical directions
+#[wasm_bindgen]
+pub fn scale_xy(width: u32, height: u32, mode_x: ScaleMode, mode_y: ScaleMode) -> bool {
+    let info = image_info();
+    if info.width == width && info.height == height {
+        return true;
+    }
 
-            result[i] = q as u8;
-        }
+    let Some(ob) = scale_buffer_xy(info, image_buffer(), width, height, mode_x, mode_y) else {
+        return false;
+    };
 
-        result
-    })
+    _set_image_buffer(ob.as_slice(), width, height)
 }
 
-/// Resize a image using bicubic interpolation
-pub fn scale_cubic(info: &ImageInfo, ib: &[u8], ob: &mut Vec<u8>, width: u32, height: u32) {
-    scale_main(info, ib, ob, width, height, |info, ib, vx, vy, sw, sh| {
-        let vx = vx - 0.5;
-        let vy = vy - 0.5;
-
-        let lx = vx.floor();
-        let ly = vy.floor();
-        let x_frac = vx - lx;
-        let y_frac = vy - ly;
-
-        let lxm1 = (lx - 1.0).clamp(0.0, sw - 1.0) as u32;
-        let lx_0 = (lx).clamp(0.0, sw - 1.0) as u32;
-        let lxp1 = (lx + 1.0).clamp(0.0, sw - 1.0) as u32;
-        let lxp2 = (lx + 2.0).clamp(0.0, sw - 1.0) as u32;
-
-        let lym1 = (ly - 1.0).clamp(0.0, sh - 1.0) as u32;
-        let ly_0 = (ly).clamp(0.0, sh - 1.0) as u32;
-        let lyp1 = (ly + 1.0).clamp(0.0, sh - 1.0) as u32;
-        let lyp2 = (ly + 2.0).clamp(0.0, sh - 1.0) as u32;
-
-        let p00 = info.get_pixel(lxm1, lym1, ib);
-        let p10 = info.get_pixel(lx_0, lym1, ib);
-        let p20 = info.get_pixel(lxp1, lym1, ib);
-        let p30 = info.get_pixel(lxp2, lym1, ib);
-
-        let p01 = info.get_pixel(lxm1, ly_0, ib);
-        let p11 = info.get_pixel(lx_0, ly_0, ib);
-        let p21 = info.get_pixel(lxp1, ly_0, ib);
-        let p31 = info.get_pixel(lxp2, ly_0, ib);
-
-        let p02 = info.get_pixel(lxm1, lyp1, ib);
-        let p12 = info.get_pixel(lx_0, lyp1, ib);
-        let p22 = info.get_pixel(lxp1, lyp1, ib);
-        let p32 = info.get_pixel(lxp2, lyp1, ib);
-
-        let p03 = info.get_pixel(lxm1, lyp2, ib);
-        let p13 = info.get_pixel(lx_0, lyp2, ib);
-        let p23 = info.get_pixel(lxp1, lyp2, ib);
-        let p33 = info.get_pixel(lxp2, lyp2, ib);
-
-        let mut result = [0u8; 4];
-        #[inline]
-        fn cubic_hermite(a: f64, b: f64, c: f64, d: f64, t: f64) -> f64 {
-            let c0 = -a / 2.0 + (3.0 * b) / 2.0 - (3.0 * c) / 2.0 + d / 2.0;
-            let c1 = a - (5.0 * b) / 2.0 + 2.0 * c - d / 2.0;
-            let c2 = -a / 2.0 + c / 2.0;
-
-            c0 * t * t * t + c1 * t * t + c2 * t + b
-        }
-        for i in 0..4 {
-            let c0 = cubic_hermite(
-                p00[i] as f64,
-                p10[i] as f64,
-                p20[i] as f64,
-                p30[i] as f64,
-                x_frac,
-            );
-            let c1 = cubic_hermite(
-                p01[i] as f64,
-                p11[i] as f64,
-                p21[i] as f64,
-                p31[i] as f64,
-                x_frac,
-            );
-            let c2 = cubic_hermite(
-                p02[i] as f64,
-                p12[i] as f64,
-                p22[i] as f64,
-                p32[i] as f64,
-                x_frac,
-            );
-            let c3 = cubic_hermite(
-                p03[i] as f64,
-                p13[i] as f64,
-                p23[i] as f64,
-                p33[i] as f64,
-                x_frac,
-            );
-            let q = cubic_hermite(c0, c1, c2, c3, y_frac);
-
-            result[i] = q as u8;
-        }
-
-        result
-    })
+/// Resize the specified buffer into a new buffer
+#[inline]
+pub fn scale_buffer(
+    info: &ImageInfo,
+    ib: &[u8],
+    width: u32,
+    height: u32,
+    mode: ScaleMode,
+) -> Option<Vec<u8>> {
+    scale_buffer_xy(info, ib, width, height, mode, mode)
 }
 
-/// Image resizing process for reduction only
-pub fn scale_reduction(info: &ImageInfo, ib: &[u8], ob: &mut Vec<u8>, width: u32, height: u32) {
-    let sw = info.width as f64;
-    let sh = info.height as f64;
-    let dw = width as f64;
-    let dh = height as f64;
-
-    #[inline(always)]
-    fn kernel(
-        info: &ImageInfo,
-        ib: &[u8],
-        x: u32,
-        y: u32,
-        sw: f64,
-        sh: f64,
-        dw: f64,
-        dh: f64,
-    ) -> [u8; 4] {
-        let vx = x as f64 * sw / dw;
-        let vy = y as f64 * sh / dh;
-
-        let lx = vx.floor() as u32;
-        let ly = vy.floor() as u32;
-        let hx = (vx + sw / dw).ceil().min(sw - 1.0) as u32;
-        let hy = (vy + sh / dh).ceil().min(sh - 1.0) as u32;
-
-        let mut acc = [0.0; 4];
-        for y in ly..hy {
-            for x in lx..hx {
-                let p = info.get_pixel(x, y, ib);
-                for ch in 0..4 {
-                    acc[ch] += p[ch] as f64;
-                }
-            }
-        }
+/// Resize the specified buffer into a new buffer with separate modes for horizontal and vertical directions
+pub fn scale_buffer_xy(
+    info: &ImageInfo,
+    ib: &[u8],
+    width: u32,
+    height: u32,
+    mode_x: ScaleMode,
+    mode_y: ScaleMode,
+) -> Option<Vec<u8>> {
+    if width < 1 || height < 1 {
+        return None;
+    }
 
-        let mut result = [0; 4];
-        let count = (hy as f64 - ly as f64) * (hx as f64 - lx as f64);
-        for i in 0..4 {
-            result[i] = (acc[i] / count) as u8
-        }
-        result
+    let mut ob = Vec::new();
+    if ob
+        .try_reserve(width as usize * height as usize * MAGIC_NUMBER)
+        .is_err()
+    {
+        return None;
     }
 
-    for y in 0..height {
-        for x in 0..width {
-            let new_pixel = kernel(info, ib, x, y, sw, sh, dw, dh);
-            ob.extend_from_slice(&new_pixel);
-        }
+    if info.width == width && info.height == height {
+        ob.extend_from_slice(ib);
+        return Some(ob);
     }
+
+    resample::resample(info, ib, &mut ob, width, height, mode_x, mode_y);
+
+    Some(ob)
 }
 
 #[wasm_bindgen]
@@ -611,84 +699,206 @@ pub fn grayscale(mode: GrayScaleMode) {
 }
 
 #[wasm_bindgen]
-pub fn posterize(fsd: bool, red: u8, green: u8, blue: u8) -> bool {
-    if red < 2 || green < 2 || blue < 2 {
+pub fn posterize(dither: DitherMode, serpentine: bool, red: u8, green: u8, blue: u8) -> bool {
+    posterize_buffer(
+        image_info(),
+        image_buffer(),
+        dither,
+        serpentine,
+        [red, green, blue, 0],
+    )
+}
+
+/// Reduce the number of levels of all channels including alpha, and dither the alpha channel too
+#[wasm_bindgen]
+pub fn posterize_rgba(
+    dither: DitherMode,
+    serpentine: bool,
+    red: u8,
+    green: u8,
+    blue: u8,
+    alpha: u8,
+) -> bool {
+    if alpha < 2 {
         return false;
     }
+    let info = image_info();
+    let ib = image_buffer();
+    let result = posterize_buffer(info, ib, dither, serpentine, [red, green, blue, alpha]);
+    update_transparency(info, ib);
+    result
+}
 
-    let table_r = make_table(red);
-    let table_g = make_table(green);
-    let table_b = make_table(blue);
+/// Invert the colors, and also the alpha channel if `include_alpha` is specified
+#[wasm_bindgen]
+pub fn invert(include_alpha: bool) {
+    let info = image_info();
+    let ib = image_buffer();
+    for pixel in ib.chunks_exact_mut(4) {
+        pixel[0] = !pixel[0];
+        pixel[1] = !pixel[1];
+        pixel[2] = !pixel[2];
+        if include_alpha {
+            pixel[3] = !pixel[3];
+        }
+    }
+    if include_alpha {
+        update_transparency(info, ib);
+    }
+}
+
+fn update_transparency(info: &mut ImageInfo, ib: &[u8]) {
+    info.transparency = ib.chunks_exact(4).any(|rgba| rgba[3] != u8::MAX).into();
+}
 
+/// Convert to black and white by the luminance
+///
+/// If `level` is not specified, it is determined by Otsu's method. Returns the level used.
+#[wasm_bindgen]
+pub fn threshold(level: Option<u8>) -> u8 {
+    let info = image_info();
     let ib = image_buffer();
-    if fsd {
-        // Floyd Steinberg Dithering
-        let info = image_info();
-        let mut errors = Vec::with_capacity(ib.len());
-        errors.resize(ib.len(), 0);
-        let errors = &mut errors;
-        for y in 0..info.height {
-            for x in 0..info.width {
-                let pixel = info.get_pixel(x, y, ib);
-                let error = info.get_pixel(x, y, errors);
-                let r = error_add(pixel[0], error[0]);
-                let g = error_add(pixel[1], error[1]);
-                let b = error_add(pixel[2], error[2]);
-                let a = pixel[3];
-
-                let new_pixel = [
-                    table_r[r.0 as usize],
-                    table_g[g.0 as usize],
-                    table_b[b.0 as usize],
-                    a,
-                ];
-                info.set_pixel(x, y, ib, new_pixel);
-
-                let e = [
-                    r.1 - new_pixel[0] as isize,
-                    g.1 - new_pixel[1] as isize,
-                    b.1 - new_pixel[2] as isize,
-                ];
-                if e != [0, 0, 0] {
-                    for (dx, dy, delta) in [(1, 0, 7), (-1, 1, 3), (0, 1, 5), (1, 1, 1)] {
-                        let x = x as isize + dx;
-                        let y = y as isize + dy;
-                        if x < 0 {
-                            continue;
-                        }
-                        let x = x as u32;
-                        let y = y as u32;
-                        if x >= info.width || y >= info.height {
-                            continue;
-                        }
-                        let mut pixel = info.get_pixel(x, y, errors);
-                        for i in 0..3 {
-                            pixel[i] = (pixel[i] as i8 as isize + (e[i] * delta / 16))
-                                .clamp(-127, 127) as u8;
-                        }
-                        info.set_pixel(x, y, errors, pixel);
-                    }
-                }
-            }
+    let level = level.unwrap_or_else(|| otsu_threshold(ib));
+    info.is_grayscale = true;
+    for pixel in ib.chunks_exact_mut(4) {
+        let value = if luminance(pixel) > level { 255 } else { 0 };
+        pixel[0] = value;
+        pixel[1] = value;
+        pixel[2] = value;
+    }
+    level
+}
+
+/// The threshold that maximizes the variance between the classes of the luminance histogram
+pub fn otsu_threshold(ib: &[u8]) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in ib.chunks_exact(4) {
+        histogram[luminance(pixel) as usize] += 1;
+    }
+    let total = histogram.iter().sum::<u64>() as f64;
+    let sum = histogram
+        .iter()
+        .enumerate()
+        .map(|(i, v)| i as f64 * *v as f64)
+        .sum::<f64>();
+
+    let mut best = (0, 0.0);
+    let mut weight_b = 0.0;
+    let mut sum_b = 0.0;
+    for (i, count) in histogram.iter().enumerate() {
+        weight_b += *count as f64;
+        let weight_f = total - weight_b;
+        if weight_b == 0.0 {
+            continue;
         }
-    } else {
-        for pixel in ib.chunks_exact_mut(4) {
-            let r = pixel[0];
-            let g = pixel[1];
-            let b = pixel[2];
-            pixel[0] = table_r[r as usize];
-            pixel[1] = table_g[g as usize];
-            pixel[2] = table_b[b as usize];
+        if weight_f == 0.0 {
+            break;
+        }
+        sum_b += i as f64 * *count as f64;
+        let mean_b = sum_b / weight_b;
+        let mean_f = (sum - sum_b) / weight_f;
+        let variance = weight_b * weight_f * (mean_b - mean_f) * (mean_b - mean_f);
+        if variance > best.1 {
+            best = (i, variance);
         }
     }
+    best.0 as u8
+}
 
+/// Apply a sepia tone
+#[wasm_bindgen]
+pub fn sepia() {
+    #[rustfmt::skip]
+    const SEPIA: [f64; 20] = [
+        0.393, 0.769, 0.189, 0.0, 0.0,
+        0.349, 0.686, 0.168, 0.0, 0.0,
+        0.272, 0.534, 0.131, 0.0, 0.0,
+        0.0, 0.0, 0.0, 1.0, 0.0,
+    ];
+    color_matrix_buffer(image_info(), image_buffer(), &SEPIA);
+}
+
+/// Apply a 4x5 color matrix (channel mixer)
+///
+/// Each row computes the red, green, blue and alpha channel of the result from the red, green, blue and alpha
+/// channels of the source and an offset (`0.0` to `255.0`), in the row-major order.
+#[wasm_bindgen]
+pub fn color_matrix(matrix: &[f64]) -> bool {
+    let Ok(matrix) = <&[f64; 20]>::try_from(matrix) else {
+        return false;
+    };
+    if matrix.iter().any(|v| !v.is_finite()) {
+        return false;
+    }
+    let info = image_info();
+    let ib = image_buffer();
+    color_matrix_buffer(info, ib, matrix);
+    if matrix[15..20] != [0.0, 0.0, 0.0, 1.0, 0.0] {
+        update_transparency(info, ib);
+    }
     true
 }
 
-fn error_add(lhs: u8, rhs: u8) -> (u8, isize) {
-    let long = lhs as isize + (rhs as i8 as isize);
-    let short = long.clamp(0, 255) as u8;
-    (short, long)
+/// Apply a 4x5 color matrix to the specified buffer
+pub fn color_matrix_buffer(info: &mut ImageInfo, ib: &mut [u8], matrix: &[f64; 20]) {
+    info.is_grayscale = false;
+    for pixel in ib.chunks_exact_mut(4) {
+        let source = [
+            pixel[0] as f64,
+            pixel[1] as f64,
+            pixel[2] as f64,
+            pixel[3] as f64,
+        ];
+        for (value, row) in pixel.iter_mut().zip(matrix.chunks_exact(5)) {
+            let result = row[0] * source[0]
+                + row[1] * source[1]
+                + row[2] * source[2]
+                + row[3] * source[3]
+                + row[4];
+            *value = result.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Reduce the number of levels per channel of the specified buffer
+///
+/// The levels are of red, green, blue and alpha, `0` of alpha leaves the alpha channel unchanged.
+pub fn posterize_buffer(
+    info: &ImageInfo,
+    ib: &mut [u8],
+    dither: DitherMode,
+    serpentine: bool,
+    levels: [u8; 4],
+) -> bool {
+    let [red, green, blue, alpha] = levels;
+    if red < 2 || green < 2 || blue < 2 || alpha == 1 {
+        return false;
+    }
+
+    let table_r = make_table(red);
+    let table_g = make_table(green);
+    let table_b = make_table(blue);
+    let table_a = (alpha > 0).then(|| make_table(alpha));
+    let spread = levels.map(|levels| 255.0 / levels.saturating_sub(1).max(1) as f64);
+
+    dither::dither_buffer(
+        info,
+        ib,
+        dither,
+        serpentine,
+        table_a.is_some(),
+        spread,
+        |pixel| {
+            [
+                table_r[pixel[0] as usize],
+                table_g[pixel[1] as usize],
+                table_b[pixel[2] as usize],
+                table_a.map_or(pixel[3], |table| table[pixel[3] as usize]),
+            ]
+        },
+    );
+
+    true
 }
 
 fn make_table(max_level: u8) -> [u8; 256] {
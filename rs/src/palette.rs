@@ -0,0 +1,564 @@
+//! Fixed palettes and color matching
+
+use crate::color::rgb_to_lab;
+use crate::dither::{self, DitherMode};
+use crate::{ImageInfo, MAGIC_NUMBER, image_buffer, image_info};
+use alloc::vec::Vec;
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BuiltinPalette {
+    /// 16 colors of CGA
+    Cga,
+    /// 64 colors of EGA
+    Ega,
+    /// 4 shades of green of the original Game Boy
+    GameBoy,
+    /// 64 colors of the NES (2C02)
+    Nes,
+    /// 16 colors of PICO-8
+    Pico8,
+    /// 216 colors of the web-safe palette
+    WebSafe,
+}
+
+#[wasm_bindgen]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DistanceMetric {
+    /// Euclidean distance in sRGB
+    Rgb,
+    /// Euclidean distance in CIE L\*a\*b\*
+    Cie76,
+    /// CIEDE2000 color difference in CIE L\*a\*b\*
+    Ciede2000,
+}
+
+#[rustfmt::skip]
+const CGA: [u32; 16] = [
+    0x000000, 0x0000aa, 0x00aa00, 0x00aaaa, 0xaa0000, 0xaa00aa, 0xaa5500, 0xaaaaaa,
+    0x555555, 0x5555ff, 0x55ff55, 0x55ffff, 0xff5555, 0xff55ff, 0xffff55, 0xffffff,
+];
+
+const GAME_BOY: [u32; 4] = [0x0f380f, 0x306230, 0x8bac0f, 0x9bbc0f];
+
+#[rustfmt::skip]
+const NES: [u32; 64] = [
+    0x7c7c7c, 0x0000fc, 0x0000bc, 0x4428bc, 0x940084, 0xa80020, 0xa81000, 0x881400,
+    0x503000, 0x007800, 0x006800, 0x005800, 0x004058, 0x000000, 0x000000, 0x000000,
+    0xbcbcbc, 0x0078f8, 0x0058f8, 0x6844fc, 0xd800cc, 0xe40058, 0xf83800, 0xe45c10,
+    0xac7c00, 0x00b800, 0x00a800, 0x00a844, 0x008888, 0x000000, 0x000000, 0x000000,
+    0xf8f8f8, 0x3cbcfc, 0x6888fc, 0x9878f8, 0xf878f8, 0xf85898, 0xf87858, 0xfca044,
+    0xf8b800, 0xb8f818, 0x58d854, 0x58f898, 0x00e8d8, 0x787878, 0x000000, 0x000000,
+    0xfcfcfc, 0xa4e4fc, 0xb8b8f8, 0xd8b8f8, 0xf8b8f8, 0xf8a4c0, 0xf0d0b0, 0xfce0a8,
+    0xf8d878, 0xd8f878, 0xb8f8b8, 0xb8f8d8, 0x00fcfc, 0xf8d8f8, 0x000000, 0x000000,
+];
+
+#[rustfmt::skip]
+const PICO8: [u32; 16] = [
+    0x000000, 0x1d2b53, 0x7e2553, 0x008751, 0xab5236, 0x5f574f, 0xc2c3c7, 0xfff1e8,
+    0xff004d, 0xffa300, 0xffec27, 0x00e436, 0x29adff, 0x83769c, 0xff77a8, 0xffccaa,
+];
+
+impl BuiltinPalette {
+    /// Colors of the palette in RGBA
+    pub fn colors(&self) -> Vec<[u8; 4]> {
+        let from_rgb = |rgb: &u32| {
+            let [_, r, g, b] = rgb.to_be_bytes();
+            [r, g, b, 255]
+        };
+        match self {
+            BuiltinPalette::Cga => CGA.iter().map(from_rgb).collect(),
+            BuiltinPalette::Ega => (0..64u8)
+                .map(|i| {
+                    // Each channel has a bit of 2/3 intensity in the lower bits and 1/3 in the upper bits
+                    let level =
+                        |shift: u8| ((i >> shift) & 1) * 0xaa + ((i >> (shift + 3)) & 1) * 0x55;
+                    [level(2), level(1), level(0), 255]
+                })
+                .collect(),
+            BuiltinPalette::GameBoy => GAME_BOY.iter().map(from_rgb).collect(),
+            BuiltinPalette::Nes => NES.iter().map(from_rgb).collect(),
+            BuiltinPalette::Pico8 => PICO8.iter().map(from_rgb).collect(),
+            BuiltinPalette::WebSafe => (0..216u8)
+                .map(|i| [i / 36 * 0x33, i / 6 % 6 * 0x33, i % 6 * 0x33, 255])
+                .collect(),
+        }
+    }
+}
+
+/// Colors of the built-in palette as a flat array of RGBA
+#[wasm_bindgen]
+pub fn builtin_palette(palette: BuiltinPalette) -> Vec<u8> {
+    palette.colors().into_iter().flatten().collect()
+}
+
+/// Map the colors of the image to the nearest colors of the palette
+///
+/// `palette` is a flat array of RGBA, and the alpha of the image is kept.
+/// Entries whose alpha is `0`, such as the transparent index of `.act`, are not matched,
+/// and the alpha of the other entries is ignored.
+#[wasm_bindgen]
+pub fn apply_palette(palette: &[u8], metric: DistanceMetric, dither: DitherMode) -> bool {
+    if palette.is_empty() || !palette.len().is_multiple_of(MAGIC_NUMBER) {
+        return false;
+    }
+    let palette = palette
+        .chunks_exact(MAGIC_NUMBER)
+        .map(|v| v.try_into().unwrap())
+        .collect::<Vec<[u8; 4]>>();
+    if palette.iter().all(|v| v[3] == 0) {
+        return false;
+    }
+    let info = image_info();
+    info.is_grayscale = false;
+    apply_palette_buffer(info, image_buffer(), &palette, metric, dither);
+    true
+}
+
+/// Map the colors of the image to the nearest colors of the built-in palette
+#[wasm_bindgen]
+pub fn apply_builtin_palette(
+    palette: BuiltinPalette,
+    metric: DistanceMetric,
+    dither: DitherMode,
+) -> bool {
+    let info = image_info();
+    info.is_grayscale = false;
+    apply_palette_buffer(info, image_buffer(), &palette.colors(), metric, dither);
+    true
+}
+
+/// Parse a palette file and return the colors as a flat array of RGBA
+///
+/// The format is detected from the contents: GIMP `.gpl`, JASC `.pal`, Adobe `.act` or a list of hex colors.
+#[wasm_bindgen]
+pub fn parse_palette(data: &[u8]) -> Option<Vec<u8>> {
+    let colors = if data.starts_with(b"GIMP Palette") {
+        parse_gpl(core::str::from_utf8(data).ok()?)
+    } else if data.starts_with(b"JASC-PAL") {
+        parse_jasc_pal(core::str::from_utf8(data).ok()?)
+    } else if let Some(colors) = core::str::from_utf8(data).ok().and_then(parse_hex_list) {
+        Some(colors)
+    } else {
+        parse_act(data)
+    }?;
+    Some(colors.into_iter().flatten().collect())
+}
+
+pub fn apply_palette_buffer(
+    info: &ImageInfo,
+    ib: &mut [u8],
+    palette: &[[u8; 4]],
+    metric: DistanceMetric,
+    dither: DitherMode,
+) {
+    // Transparent entries are not colors to match
+    let palette = palette
+        .iter()
+        .filter(|v| v[3] > 0)
+        .copied()
+        .collect::<Vec<_>>();
+    if palette.is_empty() {
+        return;
+    }
+    let matcher = PaletteMatcher::new(&palette, metric);
+    let mut cache = HashMap::new();
+    dither::dither_buffer(
+        info,
+        ib,
+        dither,
+        false,
+        false,
+        [dither::palette_spread(palette.len()); 4],
+        |pixel| {
+            // Transparent pixels absorb the error instead of passing it on
+            if pixel[3] == 0 {
+                return pixel;
+            }
+            let [r, g, b, a] = pixel;
+            let index = *cache
+                .entry([r, g, b])
+                .or_insert_with(|| matcher.nearest([r, g, b]));
+            let [r, g, b, _] = palette[index];
+            [r, g, b, a]
+        },
+    );
+}
+
+/// Nearest color search in the palette by the metric
+pub struct PaletteMatcher {
+    metric: DistanceMetric,
+    colors: Vec<[f64; 3]>,
+}
+
+impl PaletteMatcher {
+    pub fn new(palette: &[[u8; 4]], metric: DistanceMetric) -> Self {
+        let colors = palette
+            .iter()
+            .map(|v| Self::convert(metric, [v[0], v[1], v[2]]))
+            .collect();
+        Self { metric, colors }
+    }
+
+    #[inline]
+    fn convert(metric: DistanceMetric, rgb: [u8; 3]) -> [f64; 3] {
+        match metric {
+            DistanceMetric::Rgb => rgb.map(|v| v as f64),
+            DistanceMetric::Cie76 | DistanceMetric::Ciede2000 => {
+                rgb_to_lab(rgb.map(|v| v as f64 / 255.0))
+            }
+        }
+    }
+
+    /// Index of the nearest color of the palette
+    pub fn nearest(&self, rgb: [u8; 3]) -> usize {
+        let color = Self::convert(self.metric, rgb);
+        let distance = |v: &[f64; 3]| match self.metric {
+            DistanceMetric::Rgb | DistanceMetric::Cie76 => {
+                v.iter().zip(color).map(|(l, r)| (l - r) * (l - r)).sum()
+            }
+            DistanceMetric::Ciede2000 => ciede2000(*v, color),
+        };
+        self.colors
+            .iter()
+            .map(distance)
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(0, |(i, _)| i)
+    }
+}
+
+/// CIEDE2000 color difference of two colors in CIE L\*a\*b\*
+pub fn ciede2000(lhs: [f64; 3], rhs: [f64; 3]) -> f64 {
+    const POW25_7: f64 = 6103515625.0;
+    let [l1, a1, b1] = lhs;
+    let [l2, a2, b2] = rhs;
+
+    let c_bar = (a1.hypot(b1) + a2.hypot(b2)) / 2.0;
+    let c_bar7 = c_bar.powi(7);
+    let g = 0.5 * (1.0 - (c_bar7 / (c_bar7 + POW25_7)).sqrt());
+    let a1 = a1 * (1.0 + g);
+    let a2 = a2 * (1.0 + g);
+    let c1 = a1.hypot(b1);
+    let c2 = a2.hypot(b2);
+    let hue = |a: f64, b: f64| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.0)
+        }
+    };
+    let h1 = hue(a1, b1);
+    let h2 = hue(a2, b2);
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0 {
+        0.0
+    } else if h2 - h1 > 180.0 {
+        h2 - h1 - 360.0
+    } else if h2 - h1 < -180.0 {
+        h2 - h1 + 360.0
+    } else {
+        h2 - h1
+    };
+    let delta_h = 2.0 * (c1 * c2).sqrt() * (delta_h.to_radians() / 2.0).sin();
+
+    let l_bar = (l1 + l2) / 2.0;
+    let c_bar = (c1 + c2) / 2.0;
+    let h_bar = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (h_bar - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * h_bar).to_radians().cos()
+        + 0.32 * (3.0 * h_bar + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * h_bar - 63.0).to_radians().cos();
+    let delta_theta = 30.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp();
+    let c_bar7 = c_bar.powi(7);
+    let r_c = 2.0 * (c_bar7 / (c_bar7 + POW25_7)).sqrt();
+    let l50 = (l_bar - 50.0).powi(2);
+    let s_l = 1.0 + 0.015 * l50 / (20.0 + l50).sqrt();
+    let s_c = 1.0 + 0.045 * c_bar;
+    let s_h = 1.0 + 0.015 * c_bar * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+    let l = delta_l / s_l;
+    let c = delta_c / s_c;
+    let h = delta_h / s_h;
+    (l * l + c * c + h * h + r_t * c * h).max(0.0).sqrt()
+}
+
+/// Parse the values of a color line of the text formats, the rest of the line is ignored
+fn parse_color_line(line: &str, channels: usize) -> Option<[u8; 4]> {
+    let mut values = line.split_whitespace().map(|v| v.parse::<u8>().ok());
+    let mut color = [255; 4];
+    for value in color.iter_mut().take(channels) {
+        *value = values.next()??;
+    }
+    Some(color)
+}
+
+/// GIMP palette (`.gpl`)
+pub fn parse_gpl(text: &str) -> Option<Vec<[u8; 4]>> {
+    let mut lines = text.lines();
+    if lines.next()?.trim() != "GIMP Palette" {
+        return None;
+    }
+    let mut channels = 3;
+    let mut colors = Vec::new();
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            if key == "Channels" && value.trim() == "RGBA" {
+                channels = 4;
+            }
+            if !key.starts_with(|c: char| c.is_ascii_digit()) {
+                continue;
+            }
+        }
+        colors.push(parse_color_line(line, channels)?);
+    }
+    (!colors.is_empty()).then_some(colors)
+}
+
+/// JASC palette of Paint Shop Pro (`.pal`)
+pub fn parse_jasc_pal(text: &str) -> Option<Vec<[u8; 4]>> {
+    let mut lines = text.lines().map(|v| v.trim());
+    if lines.next()? != "JASC-PAL" {
+        return None;
+    }
+    let _version = lines.next()?;
+    let count = lines.next()?.parse::<usize>().ok()?;
+    let colors = lines
+        .filter(|v| !v.is_empty())
+        .map(|line| {
+            // Some variants have the alpha channel
+            let channels = line.split_whitespace().count().min(4);
+            parse_color_line(line, channels.max(3))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    (colors.len() == count && count > 0).then_some(colors)
+}
+
+/// Adobe color table (`.act`)
+///
+/// 256 colors of RGB, optionally followed by the number of colors and the index of the transparent color.
+pub fn parse_act(data: &[u8]) -> Option<Vec<[u8; 4]>> {
+    const TABLE_SIZE: usize = 256 * 3;
+    if data.len() != TABLE_SIZE && data.len() != TABLE_SIZE + 4 {
+        return None;
+    }
+    let (count, transparent) = match data.get(TABLE_SIZE..) {
+        Some([c0, c1, t0, t1]) => (
+            u16::from_be_bytes([*c0, *c1]) as usize,
+            u16::from_be_bytes([*t0, *t1]) as usize,
+        ),
+        _ => (256, usize::MAX),
+    };
+    let count = if (1..=256).contains(&count) {
+        count
+    } else {
+        256
+    };
+    Some(
+        data[..count * 3]
+            .chunks_exact(3)
+            .enumerate()
+            .map(|(i, v)| [v[0], v[1], v[2], if i == transparent { 0 } else { 255 }])
+            .collect(),
+    )
+}
+
+/// List of hex colors separated by whitespace or commas
+///
+/// Each color is `RGB`, `RRGGBB` or `RRGGBBAA` with an optional `#` or `0x` prefix.
+/// Lines starting with `;` or `//` are comments.
+pub fn parse_hex_list(text: &str) -> Option<Vec<[u8; 4]>> {
+    let mut colors = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with(';') || line.starts_with("//") {
+            continue;
+        }
+        for token in line.split(|c: char| c.is_whitespace() || c == ',') {
+            if token.is_empty() {
+                continue;
+            }
+            let hex = token
+                .strip_prefix('#')
+                .or_else(|| token.strip_prefix("0x"))
+                .or_else(|| token.strip_prefix("0X"))
+                .unwrap_or(token);
+            if !hex.bytes().all(|v| v.is_ascii_hexdigit()) {
+                return None;
+            }
+            let value = u32::from_str_radix(hex, 16).ok()?;
+            colors.push(match hex.len() {
+                3 => {
+                    let [_, _, r, gb] = value.to_be_bytes();
+                    [r * 0x11, (gb >> 4) * 0x11, (gb & 15) * 0x11, 255]
+                }
+                6 => {
+                    let [_, r, g, b] = value.to_be_bytes();
+                    [r, g, b, 255]
+                }
+                8 => value.to_be_bytes(),
+                _ => return None,
+            });
+        }
+    }
+    (!colors.is_empty()).then_some(colors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Transparency;
+
+    #[test]
+    fn gimp_palette() {
+        let text =
+            "GIMP Palette\nName: Test\nColumns: 4\n#\n255   0   0\tRed\n  0 128 255 Sky: blue\n";
+        let colors = parse_gpl(text).unwrap();
+        assert_eq!(colors, [[255, 0, 0, 255], [0, 128, 255, 255]]);
+
+        let text = "GIMP Palette\nName: Test\nChannels: RGBA\n#\n255 0 0 128 Red\n1 2 3 0 Clear\n";
+        let colors = parse_gpl(text).unwrap();
+        assert_eq!(colors, [[255, 0, 0, 128], [1, 2, 3, 0]]);
+        assert_eq!(
+            parse_palette(text.as_bytes()).unwrap(),
+            [255, 0, 0, 128, 1, 2, 3, 0]
+        );
+
+        assert_eq!(parse_gpl("GIMP Palette\nName: Empty\n#\n"), None);
+        assert_eq!(parse_gpl("GIMP Palette\n255 0\n"), None);
+        assert_eq!(parse_gpl("GIMP Palette\n256 0 0\n"), None);
+    }
+
+    #[test]
+    fn jasc_palette() {
+        let text = "JASC-PAL\r\n0100\r\n3\r\n255 0 0\r\n0 255 0\r\n0 0 255 128\r\n";
+        let colors = parse_jasc_pal(text).unwrap();
+        assert_eq!(
+            colors,
+            [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 128]]
+        );
+        assert_eq!(
+            parse_palette(text.as_bytes()).unwrap().len(),
+            3 * MAGIC_NUMBER
+        );
+
+        // The number of colors does not match the header
+        assert_eq!(
+            parse_jasc_pal("JASC-PAL\n0100\n4\n255 0 0\n0 255 0\n"),
+            None
+        );
+        assert_eq!(
+            parse_jasc_pal("JASC-PAL\n0100\n1\n255 0 0\n0 255 0\n"),
+            None
+        );
+        assert_eq!(parse_jasc_pal("JASC-PAL\n0100\n0\n"), None);
+        assert_eq!(parse_jasc_pal("JASC-PAL\n0100\nx\n255 0 0\n"), None);
+    }
+
+    #[test]
+    fn act_palette() {
+        let mut data = (0..=255u8)
+            .flat_map(|v| [v, 255 - v, v / 2])
+            .collect::<Vec<_>>();
+        let colors = parse_act(&data).unwrap();
+        assert_eq!(colors.len(), 256);
+        assert_eq!(colors[1], [1, 254, 0, 255]);
+        assert!(colors.iter().all(|v| v[3] == 255));
+
+        // The number of colors and the transparent index
+        data.extend_from_slice(&[0, 3, 0, 1]);
+        let colors = parse_act(&data).unwrap();
+        assert_eq!(colors, [[0, 255, 0, 255], [1, 254, 0, 0], [2, 253, 1, 255]]);
+        assert_eq!(parse_palette(&data).unwrap().len(), 3 * MAGIC_NUMBER);
+
+        // No transparent color
+        let len = data.len();
+        data[len - 2..].copy_from_slice(&[0xff, 0xff]);
+        assert!(parse_act(&data).unwrap().iter().all(|v| v[3] == 255));
+
+        assert_eq!(parse_act(&data[..len - 1]), None);
+        assert_eq!(parse_act(&data[..12]), None);
+    }
+
+    #[test]
+    fn hex_list() {
+        let text = "; comment\n#f00, 00ff00\n0x0000FF80 // not a comment\n";
+        assert_eq!(parse_hex_list(text), None);
+
+        let text = "; comment\n// comment\n#f00, 00ff00\n0x0000FF80\n\n";
+        let colors = parse_hex_list(text).unwrap();
+        assert_eq!(
+            colors,
+            [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 128]]
+        );
+        assert_eq!(
+            parse_palette(text.as_bytes()).unwrap().len(),
+            3 * MAGIC_NUMBER
+        );
+
+        assert_eq!(parse_hex_list("#12345"), None);
+        assert_eq!(parse_hex_list("#ggg"), None);
+        assert_eq!(parse_hex_list(" \n; empty\n"), None);
+    }
+
+    #[test]
+    fn ciede2000_reference() {
+        // Sharma, Wu and Dalal (2005), "The CIEDE2000 Color-Difference Formula"
+        let pairs = [
+            ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+            ([50.0, 3.1571, -77.2803], [50.0, 0.0, -82.7485], 2.8615),
+            ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+            ([50.0, 2.49, -0.001], [50.0, -2.49, 0.0009], 7.1792),
+            ([50.0, 2.49, -0.001], [50.0, -2.49, 0.0011], 7.2195),
+            ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+            ([50.0, 2.5, 0.0], [50.0, 3.1736, 0.5854], 1.0),
+            (
+                [60.2574, -34.0099, 36.2677],
+                [60.4626, -34.1751, 39.4387],
+                1.2644,
+            ),
+            ([2.0776, 0.0795, -1.135], [0.9033, -0.0636, -0.5514], 0.9082),
+        ];
+        for (lhs, rhs, expected) in pairs {
+            let delta = ciede2000(lhs, rhs);
+            assert!((delta - expected).abs() < 1e-4, "{lhs:?} {rhs:?}: {delta}");
+            assert!((ciede2000(rhs, lhs) - expected).abs() < 1e-4);
+        }
+        assert_eq!(ciede2000([50.0, 10.0, -10.0], [50.0, 10.0, -10.0]), 0.0);
+    }
+
+    #[test]
+    fn transparent_entries_are_not_matched() {
+        let info = ImageInfo::new(2, 1, Transparency::Translucent);
+        let mut ib = vec![10, 10, 10, 255, 200, 0, 0, 128];
+        let palette = [[0, 0, 0, 0], [250, 0, 0, 255], [255, 255, 255, 255]];
+        apply_palette_buffer(
+            &info,
+            &mut ib,
+            &palette,
+            DistanceMetric::Rgb,
+            DitherMode::None,
+        );
+        assert_eq!(ib, [250, 0, 0, 255, 250, 0, 0, 128]);
+    }
+}
@@ -0,0 +1,891 @@
+//! Scalers and tools for pixel art
+
+use crate::{_set_image_buffer, ImageInfo, MAGIC_NUMBER, image_buffer, image_info};
+use alloc::vec::Vec;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PixelArtScaler {
+    /// EPX (Scale2x, Scale3x, Scale4x)
+    Epx,
+    /// Blend the colors across the edges detected by the thresholds of hqx
+    ///
+    /// This is not hqx, which uses large lookup tables, so the results differ from hq2x, hq3x and hq4x.
+    SmoothEdges,
+    /// Smooth the diagonal edges found by the rules of xBR level 2
+    ///
+    /// This is not xBR, the edges are blended by the coverage of the sub pixels instead of the fixed weights of xBR.
+    SmoothDiagonals,
+}
+
+/// Enlarge pixel art by an integer factor (2 to 4)
+#[wasm_bindgen]
+pub fn upscale_pixel_art(factor: u32, algorithm: PixelArtScaler) -> bool {
+    let info = image_info();
+    let Some((new_info, ob)) = upscale_pixel_art_buffer(info, image_buffer(), factor, algorithm)
+    else {
+        return false;
+    };
+
+    _set_image_buffer(ob.as_slice(), new_info.width, new_info.height)
+}
+
+/// Enlarge pixel art in the specified buffer into a new buffer
+pub fn upscale_pixel_art_buffer(
+    info: &ImageInfo,
+    ib: &[u8],
+    factor: u32,
+    algorithm: PixelArtScaler,
+) -> Option<(ImageInfo, Vec<u8>)> {
+    if !(2..=4).contains(&factor) || info.width < 1 || info.height < 1 {
+        return None;
+    }
+    let width = info.width.checked_mul(factor)?;
+    let height = info.height.checked_mul(factor)?;
+    let mut ob = Vec::new();
+    if ob
+        .try_reserve(width as usize * height as usize * MAGIC_NUMBER)
+        .is_err()
+    {
+        return None;
+    }
+    ob.resize(width as usize * height as usize * MAGIC_NUMBER, 0);
+    let new_info = ImageInfo::new(width, height, info.transparency);
+
+    match (algorithm, factor) {
+        (PixelArtScaler::Epx, 2) => scale2x(info, ib, &new_info, &mut ob),
+        (PixelArtScaler::Epx, 3) => scale3x(info, ib, &new_info, &mut ob),
+        (PixelArtScaler::Epx, _) => {
+            // Scale4x is Scale2x applied twice
+            let half_info = ImageInfo::new(info.width * 2, info.height * 2, info.transparency);
+            let mut temp = vec![0; half_info.image_size()];
+            scale2x(info, ib, &half_info, &mut temp);
+            scale2x(&half_info, &temp, &new_info, &mut ob);
+        }
+        (PixelArtScaler::SmoothEdges, _) => smooth_edges(info, ib, &new_info, &mut ob, factor),
+        (PixelArtScaler::SmoothDiagonals, _) => {
+            smooth_diagonals(info, ib, &new_info, &mut ob, factor)
+        }
+    }
+
+    Some((new_info, ob))
+}
+
+/// Pixel accessor that replicates the pixels on the edges
+struct Neighbors<'a> {
+    info: &'a ImageInfo,
+    ib: &'a [u8],
+}
+
+impl Neighbors<'_> {
+    #[inline]
+    fn get(&self, x: u32, y: u32, dx: i32, dy: i32) -> [u8; 4] {
+        let x = (x as i64 + dx as i64).clamp(0, self.info.width as i64 - 1) as usize;
+        let y = (y as i64 + dy as i64).clamp(0, self.info.height as i64 - 1) as usize;
+        let offset = (x + y * self.info.width as usize) * MAGIC_NUMBER;
+        self.ib[offset..offset + MAGIC_NUMBER].try_into().unwrap()
+    }
+}
+
+#[inline]
+fn put_pixel(info: &ImageInfo, ob: &mut [u8], x: u32, y: u32, pixel: [u8; 4]) {
+    let offset = (x as usize + y as usize * info.width as usize) * MAGIC_NUMBER;
+    ob[offset..offset + MAGIC_NUMBER].copy_from_slice(&pixel);
+}
+
+fn scale2x(info: &ImageInfo, ib: &[u8], new_info: &ImageInfo, ob: &mut [u8]) {
+    let n = Neighbors { info, ib };
+    for y in 0..info.height {
+        for x in 0..info.width {
+            //   A
+            // C P B
+            //   D
+            let p = n.get(x, y, 0, 0);
+            let a = n.get(x, y, 0, -1);
+            let b = n.get(x, y, 1, 0);
+            let c = n.get(x, y, -1, 0);
+            let d = n.get(x, y, 0, 1);
+
+            let e0 = if c == a && c != d && a != b { a } else { p };
+            let e1 = if a == b && a != c && b != d { b } else { p };
+            let e2 = if d == c && d != b && c != a { c } else { p };
+            let e3 = if b == d && b != a && d != c { d } else { p };
+
+            put_pixel(new_info, ob, x * 2, y * 2, e0);
+            put_pixel(new_info, ob, x * 2 + 1, y * 2, e1);
+            put_pixel(new_info, ob, x * 2, y * 2 + 1, e2);
+            put_pixel(new_info, ob, x * 2 + 1, y * 2 + 1, e3);
+        }
+    }
+}
+
+fn scale3x(info: &ImageInfo, ib: &[u8], new_info: &ImageInfo, ob: &mut [u8]) {
+    let n = Neighbors { info, ib };
+    for y in 0..info.height {
+        for x in 0..info.width {
+            // A B C
+            // D E F
+            // G H I
+            let a = n.get(x, y, -1, -1);
+            let b = n.get(x, y, 0, -1);
+            let c = n.get(x, y, 1, -1);
+            let d = n.get(x, y, -1, 0);
+            let e = n.get(x, y, 0, 0);
+            let f = n.get(x, y, 1, 0);
+            let g = n.get(x, y, -1, 1);
+            let h = n.get(x, y, 0, 1);
+            let i = n.get(x, y, 1, 1);
+
+            let mut result = [e; 9];
+            if b != h && d != f {
+                if d == b {
+                    result[0] = d;
+                }
+                if (d == b && e != c) || (b == f && e != a) {
+                    result[1] = b;
+                }
+                if b == f {
+                    result[2] = f;
+                }
+                if (d == b && e != g) || (d == h && e != a) {
+                    result[3] = d;
+                }
+                if (b == f && e != i) || (h == f && e != c) {
+                    result[5] = f;
+                }
+                if d == h {
+                    result[6] = d;
+                }
+                if (d == h && e != i) || (h == f && e != g) {
+                    result[7] = h;
+                }
+                if h == f {
+                    result[8] = f;
+                }
+            }
+
+            for (index, pixel) in result.into_iter().enumerate() {
+                let index = index as u32;
+                put_pixel(new_info, ob, x * 3 + index % 3, y * 3 + index / 3, pixel);
+            }
+        }
+    }
+}
+
+/// Difference of colors used by hqx, the thresholds of Y, U and V are 48, 7 and 6
+#[inline]
+fn hqx_differs(lhs: [u8; 4], rhs: [u8; 4]) -> bool {
+    #[inline]
+    fn yuv(p: [u8; 4]) -> [f64; 3] {
+        let r = p[0] as f64;
+        let g = p[1] as f64;
+        let b = p[2] as f64;
+        [
+            0.299 * r + 0.587 * g + 0.114 * b,
+            -0.169 * r - 0.331 * g + 0.5 * b + 128.0,
+            0.5 * r - 0.419 * g - 0.081 * b + 128.0,
+        ]
+    }
+    if lhs == rhs {
+        return false;
+    }
+    let l = yuv(lhs);
+    let r = yuv(rhs);
+    (l[0] - r[0]).abs() > 48.0
+        || (l[1] - r[1]).abs() > 7.0
+        || (l[2] - r[2]).abs() > 6.0
+        || (lhs[3] as i32 - rhs[3] as i32).abs() > 48
+}
+
+/// Linear interpolation of two pixels, `t` is the ratio of `rhs`
+#[inline]
+fn mix(lhs: [u8; 4], rhs: [u8; 4], t: f64) -> [u8; 4] {
+    let mut result = [0; 4];
+    for ch in 0..4 {
+        result[ch] = (lhs[ch] as f64 * (1.0 - t) + rhs[ch] as f64 * t).round() as u8;
+    }
+    result
+}
+
+/// Scaler that blends the colors across the edges
+///
+/// Instead of the large lookup tables of hqx, each quadrant of the output pixel is
+/// interpolated from the center, the two adjacent edges and the corner by the same rules,
+/// using the YUV thresholds of hqx to detect the edges.
+fn smooth_edges(info: &ImageInfo, ib: &[u8], new_info: &ImageInfo, ob: &mut [u8], factor: u32) {
+    let n = Neighbors { info, ib };
+    let scale = factor as f64;
+    for y in 0..info.height {
+        for x in 0..info.width {
+            let center = n.get(x, y, 0, 0);
+            for sy in 0..factor {
+                for sx in 0..factor {
+                    // Position of the sub pixel relative to the center, -0.5 to 0.5
+                    let u = (sx as f64 + 0.5) / scale - 0.5;
+                    let v = (sy as f64 + 0.5) / scale - 0.5;
+                    let dx = if u < 0.0 { -1 } else { 1 };
+                    let dy = if v < 0.0 { -1 } else { 1 };
+
+                    let horizontal = n.get(x, y, dx, 0);
+                    let vertical = n.get(x, y, 0, dy);
+                    let corner = n.get(x, y, dx, dy);
+
+                    // Distance toward the corner, 0 at the center and 1 at the corner
+                    let du = u.abs() * 2.0;
+                    let dv = v.abs() * 2.0;
+
+                    let h_differs = hqx_differs(center, horizontal);
+                    let v_differs = hqx_differs(center, vertical);
+                    let pixel = if h_differs && v_differs && !hqx_differs(horizontal, vertical) {
+                        // Diagonal edge across the corner
+                        let edge = mix(horizontal, vertical, 0.5);
+                        let t = (du + dv - 0.5).clamp(0.0, 0.75);
+                        if hqx_differs(center, corner) {
+                            mix(center, edge, t)
+                        } else {
+                            // The center color wraps around the corner
+                            mix(center, edge, t * 0.5)
+                        }
+                    } else if !h_differs && !v_differs && hqx_differs(center, corner) {
+                        // Isolated corner
+                        mix(center, corner, (du * dv).min(0.25))
+                    } else {
+                        center
+                    };
+
+                    put_pixel(new_info, ob, x * factor + sx, y * factor + sy, pixel);
+                }
+            }
+        }
+    }
+}
+
+/// Weighted distance of colors used by xBR
+#[inline]
+fn xbr_distance(lhs: [u8; 4], rhs: [u8; 4]) -> f64 {
+    #[inline]
+    fn luma(p: [u8; 4]) -> f64 {
+        (14.352 * p[0] as f64 + 28.176 * p[1] as f64 + 5.472 * p[2] as f64) / 255.0
+    }
+    (luma(lhs) - luma(rhs)).abs() + (lhs[3] as f64 - rhs[3] as f64).abs() * (48.0 / 255.0)
+}
+
+#[inline]
+fn xbr_eq(lhs: [u8; 4], rhs: [u8; 4]) -> bool {
+    const THRESHOLD: f64 = 15.0;
+    xbr_distance(lhs, rhs) < THRESHOLD
+}
+
+/// Scaler that smooths the diagonal edges
+///
+/// The edges are detected by the rules of xBR level 2, and the sub pixels are blended by the area covered by the edge.
+fn smooth_diagonals(info: &ImageInfo, ib: &[u8], new_info: &ImageInfo, ob: &mut [u8], factor: u32) {
+    let n = Neighbors { info, ib };
+    let scale = factor as f64;
+    // Width of the smoothed edge
+    let delta = 1.0 / scale;
+
+    for y in 0..info.height {
+        for x in 0..info.width {
+            let e = n.get(x, y, 0, 0);
+            let mut block = [e; 16];
+
+            // Each corner is processed as the bottom right corner by mirroring
+            for (mx, my) in [(1, 1), (-1, 1), (-1, -1), (1, -1)] {
+                let p = |dx: i32, dy: i32| n.get(x, y, dx * mx, dy * my);
+                let b = p(0, -1);
+                let c = p(1, -1);
+                let d = p(-1, 0);
+                let f = p(1, 0);
+                let f4 = p(2, 0);
+                let g = p(-1, 1);
+                let h = p(0, 1);
+                let i = p(1, 1);
+                let i4 = p(2, 1);
+                let h5 = p(0, 2);
+                let i5 = p(1, 2);
+
+                let restriction_lv1 = e != f
+                    && e != h
+                    && ((!xbr_eq(f, b) && !xbr_eq(h, d))
+                        || (xbr_eq(e, i) && !xbr_eq(f, i4) && !xbr_eq(h, i5))
+                        || xbr_eq(e, g)
+                        || xbr_eq(e, c));
+                if !restriction_lv1 {
+                    continue;
+                }
+                let wd1 = xbr_distance(e, c)
+                    + xbr_distance(e, g)
+                    + xbr_distance(i, h5)
+                    + xbr_distance(i, f4)
+                    + 4.0 * xbr_distance(h, f);
+                let wd2 = xbr_distance(h, d)
+                    + xbr_distance(h, i5)
+                    + xbr_distance(f, i4)
+                    + xbr_distance(f, b)
+                    + 4.0 * xbr_distance(e, i);
+                if wd1 >= wd2 {
+                    continue;
+                }
+
+                let shallow = xbr_distance(f, g) * 2.0 <= xbr_distance(h, c) && e != g && d != g;
+                let steep = xbr_distance(f, g) >= xbr_distance(h, c) * 2.0 && e != c && b != c;
+                let new_color = if xbr_distance(e, f) <= xbr_distance(e, h) {
+                    f
+                } else {
+                    h
+                };
+
+                for sy in 0..factor {
+                    for sx in 0..factor {
+                        // Position of the sub pixel in the mirrored coordinates, 0 to 1
+                        let mut u = (sx as f64 + 0.5) / scale;
+                        let mut v = (sy as f64 + 0.5) / scale;
+                        if mx < 0 {
+                            u = 1.0 - u;
+                        }
+                        if my < 0 {
+                            v = 1.0 - v;
+                        }
+
+                        let coverage = |value: f64, threshold: f64| {
+                            ((value - threshold) / (2.0 * delta) + 0.5).clamp(0.0, 1.0)
+                        };
+                        let mut t = coverage(u + v, 1.5);
+                        if shallow {
+                            t = t.max(coverage(v + 0.5 * u, 1.0));
+                        }
+                        if steep {
+                            t = t.max(coverage(v * 0.5 + u, 1.0));
+                        }
+                        if t > 0.0 {
+                            let index = (sx + sy * factor) as usize;
+                            block[index] = mix(block[index], new_color, t);
+                        }
+                    }
+                }
+            }
+
+            for sy in 0..factor {
+                for sx in 0..factor {
+                    let pixel = block[(sx + sy * factor) as usize];
+                    put_pixel(new_info, ob, x * factor + sx, y * factor + sy, pixel);
+                }
+            }
+        }
+    }
+}
+
+/// Grid of the pixels of upscaled pixel art
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PixelGrid {
+    /// Width of each pixel of the original art
+    pub scale_x: f64,
+    /// Height of each pixel of the original art
+    pub scale_y: f64,
+    /// Position of the first grid line from the left edge
+    pub offset_x: f64,
+    /// Position of the first grid line from the top edge
+    pub offset_y: f64,
+}
+
+/// Minimum coherence of the grid lines to accept the detected period
+const GRID_COHERENCE_THRESHOLD: f64 = 0.7;
+
+/// Reduce upscaled pixel art to its original resolution
+///
+/// Unlike scaling by the factor of `get_pixel_scale`, this detects the offset of the grid and non-integer scales,
+/// and each pixel is restored from the dominant or median color of its cell, so it also works on JPEG-damaged images.
+/// Returns the detected grid, or `None` if no grid was detected.
+#[wasm_bindgen]
+pub fn restore_pixel_art(max_color_diff: u8) -> Option<PixelGrid> {
+    let info = image_info();
+    let ib = image_buffer();
+    let grid = detect_pixel_grid(info, ib, max_color_diff)?;
+    let (new_info, ob) = restore_pixel_art_buffer(info, ib, &grid)?;
+
+    _set_image_buffer(ob.as_slice(), new_info.width, new_info.height).then_some(grid)
+}
+
+/// Detect the grid of upscaled pixel art
+///
+/// Colors that differ by at most `max_color_diff` in every channel are regarded as the same.
+pub fn detect_pixel_grid(info: &ImageInfo, ib: &[u8], max_color_diff: u8) -> Option<PixelGrid> {
+    let width = info.width as usize;
+    let height = info.height as usize;
+    if width < 2 || height < 2 {
+        return None;
+    }
+    let differs = |lhs: usize, rhs: usize| {
+        let lhs = &ib[lhs * MAGIC_NUMBER..(lhs + 1) * MAGIC_NUMBER];
+        let rhs = &ib[rhs * MAGIC_NUMBER..(rhs + 1) * MAGIC_NUMBER];
+        lhs.iter()
+            .zip(rhs)
+            .any(|(l, r)| l.abs_diff(*r) > max_color_diff)
+    };
+
+    // Number of color changes at each boundary between columns and rows
+    let mut edges_x = vec![0u32; width];
+    let mut edges_y = vec![0u32; height];
+    for (y, edge_y) in edges_y.iter_mut().enumerate() {
+        for (x, edge_x) in edges_x.iter_mut().enumerate() {
+            let index = x + y * width;
+            if x > 0 && differs(index - 1, index) {
+                *edge_x += 1;
+            }
+            if y > 0 && differs(index - width, index) {
+                *edge_y += 1;
+            }
+        }
+    }
+
+    match (detect_period(&edges_x), detect_period(&edges_y)) {
+        (Some((scale_x, offset_x)), Some((scale_y, offset_y))) => Some(PixelGrid {
+            scale_x,
+            scale_y,
+            offset_x,
+            offset_y,
+        }),
+        // Pixels are assumed to be square if one of the directions has no boundaries
+        (Some((scale_x, offset_x)), None) => Some(PixelGrid {
+            scale_x,
+            scale_y: scale_x,
+            offset_x,
+            offset_y: 0.0,
+        }),
+        (None, Some((scale_y, offset_y))) => Some(PixelGrid {
+            scale_x: scale_y,
+            scale_y,
+            offset_x: 0.0,
+            offset_y,
+        }),
+        (None, None) => None,
+    }
+}
+
+/// Smallest period detected, below the smallest common scale of 1.5
+const MIN_GRID_PERIOD: f64 = 1.25;
+
+/// Number of the peaks of the autocorrelation used as the seeds of the period
+const GRID_SEED_LAGS: usize = 8;
+
+/// Maximum number of cells spanned by a seed lag
+const GRID_SEED_CELLS: usize = 8;
+
+/// Find the period and phase of the boundaries
+///
+/// Each boundary is mapped onto a circle of the circumference of the candidate period,
+/// and the period at which the boundaries gather at one point is taken.
+/// The candidates are seeded from the peaks of the autocorrelation of the boundaries and refined locally.
+/// Divisors of the true period also gather, so the largest coherent period is selected.
+fn detect_period(edges: &[u32]) -> Option<(f64, f64)> {
+    use core::f64::consts::{PI, TAU};
+
+    let len = edges.len() as f64;
+    // At least two whole cells are required
+    let max_period = (len / 2.0).min(256.0);
+    let positions = edges
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count > 0)
+        .map(|(position, count)| (position as f64, *count as f64))
+        .collect::<Vec<_>>();
+    if positions.len() < 2 {
+        return None;
+    }
+
+    // Coherence and phase of the boundaries before `limit`
+    let coherence_within = |period: f64, limit: f64| {
+        let (mut re, mut im, mut total) = (0.0, 0.0, 0.0);
+        for (position, count) in positions.iter().take_while(|v| v.0 < limit) {
+            let (sin, cos) = (position / period * TAU).sin_cos();
+            re += cos * count;
+            im += sin * count;
+            total += count;
+        }
+        if total == 0.0 {
+            return (0.0, 0.0);
+        }
+        ((re * re + im * im).sqrt() / total, im.atan2(re))
+    };
+    let coherence = |period: f64| coherence_within(period, len);
+
+    // The peak of the coherence is about `period^2 / limit` wide,
+    // so the range of the search narrows as more boundaries are taken into account.
+    let refine = |seed: f64, radius: f64| {
+        let mut center = seed;
+        let mut radius = radius;
+        loop {
+            let step = radius / 8.0;
+            let limit = (center * center / (step * 2.0)).max(center * 4.0);
+            center = (-8..=8)
+                .map(|i| center + step * i as f64)
+                .filter(|v| (MIN_GRID_PERIOD..=max_period).contains(v))
+                .map(|v| (v, coherence_within(v, limit).0))
+                .max_by(|a, b| a.1.total_cmp(&b.1))?
+                .0;
+            radius = step;
+            if limit >= len && step < center * center / len / 32.0 {
+                return Some(center);
+            }
+        }
+    };
+
+    // Boundaries of non-integer periods are rounded to whole pixels,
+    // which spreads them over a pixel and lowers the coherence by `sinc(1 / period)`.
+    let threshold = |period: f64| {
+        let x = PI / period;
+        GRID_COHERENCE_THRESHOLD * x.sin() / x
+    };
+
+    // Lags at which the boundaries repeat, ordered by the strength
+    let max_lag = max_period as usize;
+    let autocorrelation = (0..=max_lag + 1)
+        .map(|lag| {
+            edges
+                .iter()
+                .zip(edges.iter().skip(lag))
+                .map(|(a, b)| *a as u64 * *b as u64)
+                .sum::<u64>()
+        })
+        .collect::<Vec<_>>();
+    let mut lags = (1..=max_lag)
+        .filter(|&lag| {
+            autocorrelation[lag] > 0
+                && autocorrelation[lag] >= autocorrelation[lag - 1]
+                && autocorrelation[lag] >= autocorrelation[lag + 1]
+        })
+        .collect::<Vec<_>>();
+    lags.sort_by_key(|&lag| core::cmp::Reverse(autocorrelation[lag]));
+    lags.truncate(GRID_SEED_LAGS);
+
+    // Each lag spans an unknown number of cells, and is off by up to half a pixel
+    let mut best: Option<f64> = None;
+    for lag in lags {
+        for cells in 1..=GRID_SEED_CELLS {
+            let seed = lag as f64 / cells as f64;
+            if seed < MIN_GRID_PERIOD || best.is_some_and(|v| seed + 0.5 / cells as f64 <= v) {
+                continue;
+            }
+            let Some(period) = refine(seed, 0.5 / cells as f64) else {
+                continue;
+            };
+            if coherence(period).0 >= threshold(period) && best.is_none_or(|v| period > v) {
+                best = Some(period);
+            }
+        }
+    }
+    let mut period = best?;
+
+    // Prefer integer scales if they are as good as the peak
+    let integer = period.round();
+    if coherence(integer).0 >= coherence(period).0 - 0.02 {
+        period = integer;
+    }
+    let phase = coherence(period).1;
+    let mut offset = (phase / TAU * period).rem_euclid(period);
+    if period == integer {
+        offset = offset.round().rem_euclid(period);
+    }
+    Some((period, offset))
+}
+
+/// Reduce the buffer to one pixel per cell of the grid
+pub fn restore_pixel_art_buffer(
+    info: &ImageInfo,
+    ib: &[u8],
+    grid: &PixelGrid,
+) -> Option<(ImageInfo, Vec<u8>)> {
+    let cells_x = grid_cells(info.width, grid.scale_x, grid.offset_x)?;
+    let cells_y = grid_cells(info.height, grid.scale_y, grid.offset_y)?;
+    let width = cells_x.len() as u32;
+    let height = cells_y.len() as u32;
+
+    let mut ob = Vec::new();
+    if ob
+        .try_reserve(width as usize * height as usize * MAGIC_NUMBER)
+        .is_err()
+    {
+        return None;
+    }
+    let mut samples = Vec::new();
+    for range_y in cells_y.iter() {
+        for range_x in cells_x.iter() {
+            samples.clear();
+            for y in range_y.clone() {
+                for x in range_x.clone() {
+                    samples.push(info.get_pixel(x, y, ib));
+                }
+            }
+            ob.extend_from_slice(&representative_color(&mut samples));
+        }
+    }
+
+    Some((ImageInfo::new(width, height, info.transparency), ob))
+}
+
+/// Ranges of the pixels sampled from each cell
+///
+/// Partial cells on the edges are kept only if at least half of them is visible.
+/// The borders of each cell are excluded, because they are often blurred by resampling or compression.
+fn grid_cells(len: u32, scale: f64, offset: f64) -> Option<Vec<core::ops::Range<u32>>> {
+    if !(scale.is_finite() && offset.is_finite()) || scale < 1.0 {
+        return None;
+    }
+    let offset = offset.rem_euclid(scale);
+    let start = if offset * 2.0 >= scale {
+        offset - scale
+    } else {
+        offset
+    };
+    let count = ((len as f64 - start) / scale).round() as u32;
+    if count < 1 {
+        return None;
+    }
+
+    let margin = if scale >= 3.0 { scale * 0.2 } else { 0.0 };
+    let cells = (0..count)
+        .map(|i| {
+            let left = start + i as f64 * scale;
+            let right = left + scale;
+            let from = ((left + margin).max(0.0).round() as u32).min(len - 1);
+            let to = ((right - margin).min(len as f64).round() as u32).min(len);
+            if from < to {
+                from..to
+            } else {
+                let center = ((left + right) / 2.0).clamp(0.0, len as f64 - 1.0) as u32;
+                center..center + 1
+            }
+        })
+        .collect();
+    Some(cells)
+}
+
+/// The most frequent color if it covers the majority of the samples, otherwise the median of each channel
+fn representative_color(samples: &mut [[u8; 4]]) -> [u8; 4] {
+    samples.sort_unstable();
+    let mut dominant = (samples[0], 0);
+    for run in samples.chunk_by(|a, b| a == b) {
+        if run.len() > dominant.1 {
+            dominant = (run[0], run.len());
+        }
+    }
+    if dominant.1 * 2 > samples.len() {
+        return dominant.0;
+    }
+
+    let mut result = [0; 4];
+    let mut values = Vec::with_capacity(samples.len());
+    for (ch, result) in result.iter_mut().enumerate() {
+        values.clear();
+        values.extend(samples.iter().map(|v| v[ch]));
+        let mid = values.len() / 2;
+        *result = *values.select_nth_unstable(mid).1;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Transparency;
+
+    const PALETTE: [[u8; 4]; 6] = [
+        [0, 0, 0, 255],
+        [255, 255, 255, 255],
+        [200, 40, 40, 255],
+        [40, 160, 60, 255],
+        [30, 60, 200, 255],
+        [240, 200, 30, 255],
+    ];
+
+    /// Pseudorandom art of the colors of `PALETTE`
+    fn art(width: u32, height: u32) -> (ImageInfo, Vec<u8>) {
+        let mut seed = 0x2545_f491u32;
+        let mut ib = Vec::new();
+        for _ in 0..width * height {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            ib.extend_from_slice(&PALETTE[(seed >> 16) as usize % PALETTE.len()]);
+        }
+        (ImageInfo::new(width, height, Transparency::Opaque), ib)
+    }
+
+    /// Nearest neighbor upscale, shifted to the left and up by `shift` pixels
+    fn upscale(info: &ImageInfo, ib: &[u8], scale: f64, shift: f64) -> (ImageInfo, Vec<u8>) {
+        let width = (info.width as f64 * scale - shift).round() as u32;
+        let height = (info.height as f64 * scale - shift).round() as u32;
+        let cell = |v: u32, len: u32| (((v as f64 + shift) / scale) as u32).min(len - 1);
+        let new_info = ImageInfo::new(width, height, info.transparency);
+        let mut ob = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = info.get_pixel(cell(x, info.width), cell(y, info.height), ib);
+                ob.extend_from_slice(&pixel);
+            }
+        }
+        (new_info, ob)
+    }
+
+    /// Art of the characters, `K` is black, `W` is white, `R` is red and `.` is transparent
+    fn from_rows(rows: &[&str]) -> (ImageInfo, Vec<u8>) {
+        let ib = rows
+            .iter()
+            .flat_map(|row| row.bytes())
+            .flat_map(|ch| match ch {
+                b'K' => [0, 0, 0, 255],
+                b'W' => [255, 255, 255, 255],
+                b'R' => [255, 0, 0, 255],
+                _ => [0; 4],
+            })
+            .collect();
+        let info = ImageInfo::new(
+            rows[0].len() as u32,
+            rows.len() as u32,
+            Transparency::Translucent,
+        );
+        (info, ib)
+    }
+
+    #[test]
+    fn scale2x_matches_reference() {
+        let (info, ib) = from_rows(&["KWW", "WKW", "WWK"]);
+        let (new_info, ob) = upscale_pixel_art_buffer(&info, &ib, 2, PixelArtScaler::Epx).unwrap();
+        #[rustfmt::skip]
+        let expected = from_rows(&[
+            "KKWWWW",
+            "KWKWWW",
+            "WKKKWW",
+            "WWKKKW",
+            "WWWKWK",
+            "WWWWKK",
+        ]);
+        assert_eq!((new_info.width, new_info.height), (6, 6));
+        assert!(ob == expected.1);
+    }
+
+    #[test]
+    fn scale3x_matches_reference() {
+        let (info, ib) = from_rows(&["KWW", "WKW", "WWK"]);
+        let (new_info, ob) = upscale_pixel_art_buffer(&info, &ib, 3, PixelArtScaler::Epx).unwrap();
+        #[rustfmt::skip]
+        let expected = from_rows(&[
+            "KKKWWWWWW",
+            "KKWKWWWWW",
+            "KWWKWWWWW",
+            "WKKKKKWWW",
+            "WWWKKKWWW",
+            "WWWKKKKKW",
+            "WWWWWKWWK",
+            "WWWWWKWKK",
+            "WWWWWWKKK",
+        ]);
+        assert_eq!((new_info.width, new_info.height), (9, 9));
+        assert!(ob == expected.1);
+    }
+
+    #[test]
+    fn transparent_pixels_stay_transparent() {
+        #[rustfmt::skip]
+        let rows = [
+            "........",
+            "........",
+            "...RR...",
+            "..RKKR..",
+            "..RKKR..",
+            "...RR...",
+            "........",
+            "........",
+        ];
+        let (info, ib) = from_rows(&rows);
+        for algorithm in [
+            PixelArtScaler::Epx,
+            PixelArtScaler::SmoothEdges,
+            PixelArtScaler::SmoothDiagonals,
+        ] {
+            for factor in 2..=4 {
+                let (new_info, ob) =
+                    upscale_pixel_art_buffer(&info, &ib, factor, algorithm).unwrap();
+                for y in 0..new_info.height {
+                    for x in 0..new_info.width {
+                        // Pixels whose neighbors within 2 pixels are all transparent
+                        let (sx, sy) = (x / factor, y / factor);
+                        let isolated = (sx.saturating_sub(2)..(sx + 3).min(info.width)).all(|nx| {
+                            (sy.saturating_sub(2)..(sy + 3).min(info.height))
+                                .all(|ny| info.get_pixel(nx, ny, &ib)[3] == 0)
+                        });
+                        let pixel = new_info.get_pixel(x, y, &ob);
+                        if isolated {
+                            assert_eq!(pixel, [0; 4], "{algorithm:?} {factor} ({x}, {y})");
+                        }
+                        if algorithm == PixelArtScaler::Epx {
+                            assert!(pixel[3] == 0 || pixel[3] == 255);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn detects_integer_grids() {
+        let (info, ib) = art(24, 16);
+        for scale in [2.0, 3.0, 4.0, 8.0] {
+            let (info, ib) = upscale(&info, &ib, scale, 0.0);
+            let grid = detect_pixel_grid(&info, &ib, 0).unwrap();
+            let expected = PixelGrid {
+                scale_x: scale,
+                scale_y: scale,
+                offset_x: 0.0,
+                offset_y: 0.0,
+            };
+            assert_eq!(grid, expected);
+        }
+    }
+
+    #[test]
+    fn detects_offset_grids() {
+        let (info, ib) = art(24, 16);
+        for (scale, shift) in [(3.0, 1.0), (4.0, 1.0), (4.0, 3.0), (5.0, 2.0)] {
+            let (info, ib) = upscale(&info, &ib, scale, shift);
+            let grid = detect_pixel_grid(&info, &ib, 0).unwrap();
+            assert_eq!(
+                (grid.scale_x, grid.scale_y),
+                (scale, scale),
+                "{scale} {shift}"
+            );
+            assert_eq!(grid.offset_x, scale - shift, "{scale} {shift}");
+            assert_eq!(grid.offset_y, scale - shift, "{scale} {shift}");
+        }
+    }
+
+    #[test]
+    fn detects_non_integer_grids() {
+        let (info, ib) = art(40, 30);
+        for scale in [1.5, 2.5, 3.5, 4.25] {
+            let (info, ib) = upscale(&info, &ib, scale, 0.0);
+            let grid = detect_pixel_grid(&info, &ib, 0).unwrap();
+            assert!((grid.scale_x - scale).abs() < 0.01, "{scale}: {grid:?}");
+            assert!((grid.scale_y - scale).abs() < 0.01, "{scale}: {grid:?}");
+        }
+    }
+
+    #[test]
+    fn restores_original_art() {
+        let (info, ib) = art(40, 30);
+        for scale in [1.5, 2.0, 2.5, 3.0, 3.5, 4.0, 6.0] {
+            let (upscaled_info, upscaled) = upscale(&info, &ib, scale, 0.0);
+            let grid = detect_pixel_grid(&upscaled_info, &upscaled, 0).unwrap();
+            let (new_info, ob) =
+                restore_pixel_art_buffer(&upscaled_info, &upscaled, &grid).unwrap();
+            assert_eq!(
+                (new_info.width, new_info.height),
+                (info.width, info.height),
+                "{scale}"
+            );
+            assert!(ob == ib, "{scale}");
+        }
+    }
+}
@@ -0,0 +1,430 @@
+//! Color quantization to an adaptive palette
+
+use crate::dither::{self, DitherMode};
+use crate::{ImageInfo, MAGIC_NUMBER, image_buffer, image_info};
+use alloc::vec::Vec;
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+/// Maximum number of entries of the color histogram, more colors are merged by dropping the lower bits
+const MAX_HISTOGRAM_LEN: usize = 0x1_0000;
+
+/// Maximum number of iterations of k-means
+const KMEANS_MAX_ITERATIONS: usize = 16;
+
+#[wasm_bindgen]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QuantizeAlgorithm {
+    MedianCut,
+    Octree,
+    /// Median cut refined by k-means
+    KMeans,
+}
+
+/// Reduce the colors of the image to an adaptive palette of up to `max_colors` (`1` to `256`) colors
+///
+/// Returns the palette as a flat array of RGBA.
+#[wasm_bindgen]
+pub fn quantize(
+    max_colors: u32,
+    algorithm: QuantizeAlgorithm,
+    dither: DitherMode,
+) -> Option<Vec<u8>> {
+    let info = image_info();
+    let ib = image_buffer();
+    let palette = quantize_buffer(info, ib, max_colors, algorithm, dither)?;
+    crate::update_transparency(info, ib);
+    Some(palette.into_iter().flatten().collect())
+}
+
+/// Quantize the specified buffer and return the palette
+pub fn quantize_buffer(
+    info: &ImageInfo,
+    ib: &mut [u8],
+    max_colors: u32,
+    algorithm: QuantizeAlgorithm,
+    dither: DitherMode,
+) -> Option<Vec<[u8; 4]>> {
+    let palette = build_palette(ib, max_colors, algorithm)?;
+    remap_buffer(info, ib, &palette, dither);
+    Some(palette)
+}
+
+/// Make an adaptive palette of up to `max_colors` (`1` to `256`) colors for the buffer
+pub fn build_palette(
+    ib: &[u8],
+    max_colors: u32,
+    algorithm: QuantizeAlgorithm,
+) -> Option<Vec<[u8; 4]>> {
+    if !(1..=256).contains(&max_colors) {
+        return None;
+    }
+    let histogram = ColorHistogram::new(ib)?;
+    // Fully transparent pixels have an entry of their own at the beginning of the palette
+    let mut palette = Vec::new();
+    if histogram.has_transparent {
+        palette.push([0; 4]);
+    }
+    let max_colors = max_colors as usize - palette.len();
+    palette.extend(cluster_colors(&histogram, max_colors, algorithm));
+    Some(palette)
+}
+
+/// Make an adaptive palette of up to `max_colors` (`1` to `256`) colors for the visible pixels of the buffer
+///
+/// Unlike `build_palette`, fully transparent pixels are ignored and take no entry.
+pub fn build_visible_palette(
+    ib: &[u8],
+    max_colors: u32,
+    algorithm: QuantizeAlgorithm,
+) -> Option<Vec<[u8; 4]>> {
+    if !(1..=256).contains(&max_colors) {
+        return None;
+    }
+    let histogram = ColorHistogram::new(ib)?;
+    Some(cluster_colors(&histogram, max_colors as usize, algorithm))
+}
+
+fn cluster_colors(
+    histogram: &ColorHistogram,
+    max_colors: usize,
+    algorithm: QuantizeAlgorithm,
+) -> Vec<[u8; 4]> {
+    if histogram.entries.is_empty() || max_colors == 0 {
+        return Vec::new();
+    }
+    match algorithm {
+        QuantizeAlgorithm::MedianCut => median_cut(histogram, max_colors),
+        QuantizeAlgorithm::Octree => octree(histogram, max_colors),
+        QuantizeAlgorithm::KMeans => {
+            let initial = median_cut(histogram, max_colors);
+            kmeans(histogram, initial)
+        }
+    }
+}
+
+/// Replace each pixel of the buffer with the nearest color of the palette
+pub fn remap_buffer(info: &ImageInfo, ib: &mut [u8], palette: &[[u8; 4]], dither: DitherMode) {
+    if palette.is_empty() {
+        return;
+    }
+    let spread = dither::palette_spread(palette.len());
+    // The hidden colors of transparent pixels must not diffuse into the neighbors
+    for pixel in ib.chunks_exact_mut(MAGIC_NUMBER) {
+        if pixel[3] == 0 {
+            pixel.fill(0);
+        }
+    }
+    let mut cache = HashMap::new();
+    dither::dither_buffer(
+        info,
+        ib,
+        dither,
+        false,
+        info.is_translucent(),
+        [spread; 4],
+        |pixel| {
+            *cache
+                .entry(u32::from_be_bytes(pixel))
+                .or_insert_with(|| palette[nearest_color(palette, normalize(pixel))])
+        },
+    );
+}
+
+/// Index of the nearest color of the palette
+pub fn nearest_color(palette: &[[u8; 4]], color: [u8; 4]) -> usize {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, v)| color_distance(**v, color))
+        .map_or(0, |(i, _)| i)
+}
+
+/// Squared distance of two colors including alpha
+#[inline]
+fn color_distance(lhs: [u8; 4], rhs: [u8; 4]) -> u32 {
+    lhs.iter()
+        .zip(rhs)
+        .map(|(l, r)| {
+            let d = *l as i32 - r as i32;
+            (d * d) as u32
+        })
+        .sum()
+}
+
+/// Fully transparent pixels are treated as the same color regardless of their RGB
+#[inline]
+pub fn normalize(pixel: [u8; 4]) -> [u8; 4] {
+    if pixel[3] == 0 { [0; 4] } else { pixel }
+}
+
+/// Distinct colors of the image with the number of pixels of each
+struct ColorHistogram {
+    /// Colors except fully transparent ones
+    entries: Vec<HistogramEntry>,
+    has_transparent: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct HistogramEntry {
+    color: [u8; 4],
+    count: u32,
+}
+
+impl ColorHistogram {
+    fn new(ib: &[u8]) -> Option<Self> {
+        let mut colors = Vec::new();
+        colors.try_reserve(ib.len() / MAGIC_NUMBER).ok()?;
+        colors.extend(
+            ib.chunks_exact(MAGIC_NUMBER)
+                .filter(|v| v[3] > 0)
+                .map(|v| u32::from_be_bytes(v.try_into().unwrap())),
+        );
+        let has_transparent = colors.len() < ib.len() / MAGIC_NUMBER;
+
+        // Drop the lower bits until the histogram is small enough, the entries hold the mean color of the merged colors
+        for shift in 0.. {
+            let mask = u32::from_be_bytes([0xff << shift; 4]);
+            colors.sort_unstable_by_key(|v| v & mask);
+            let mut sums = Vec::new();
+            let mut last = None;
+            let mut overflow = false;
+            for color in colors.iter() {
+                let key = color & mask;
+                if last != Some(key) {
+                    if sums.len() >= MAX_HISTOGRAM_LEN {
+                        overflow = true;
+                        break;
+                    }
+                    last = Some(key);
+                    sums.push(([0u64; 4], 0u32));
+                }
+                let (sum, count) = sums.last_mut().unwrap();
+                for (acc, v) in sum.iter_mut().zip(color.to_be_bytes()) {
+                    *acc += v as u64;
+                }
+                *count += 1;
+            }
+            if !overflow {
+                let entries = sums
+                    .into_iter()
+                    .map(|(sum, count)| HistogramEntry {
+                        color: sum.map(|v| ((v + count as u64 / 2) / count as u64) as u8),
+                        count,
+                    })
+                    .collect();
+                return Some(Self {
+                    entries,
+                    has_transparent,
+                });
+            }
+        }
+        unreachable!()
+    }
+}
+
+/// Weighted mean color of the entries
+fn mean_color(entries: &[HistogramEntry]) -> [u8; 4] {
+    let mut sum = [0u64; 4];
+    let mut total = 0u64;
+    for entry in entries {
+        for (acc, v) in sum.iter_mut().zip(entry.color) {
+            *acc += v as u64 * entry.count as u64;
+        }
+        total += entry.count as u64;
+    }
+    if total == 0 {
+        return [0; 4];
+    }
+    sum.map(|v| ((v + total / 2) / total) as u8)
+}
+
+/// Range of the histogram entries for median cut
+struct ColorBox {
+    start: usize,
+    end: usize,
+    /// Sum of the squared errors of the channel with the largest variance
+    error: f64,
+    channel: usize,
+}
+
+impl ColorBox {
+    fn new(entries: &[HistogramEntry], start: usize, end: usize) -> Self {
+        let mut sum = [0.0f64; 4];
+        let mut sum_sq = [0.0f64; 4];
+        let mut total = 0.0;
+        for entry in entries[start..end].iter() {
+            let count = entry.count as f64;
+            for ch in 0..MAGIC_NUMBER {
+                let v = entry.color[ch] as f64;
+                sum[ch] += v * count;
+                sum_sq[ch] += v * v * count;
+            }
+            total += count;
+        }
+        let (channel, error) = (0..MAGIC_NUMBER)
+            .map(|ch| (ch, sum_sq[ch] - sum[ch] * sum[ch] / total))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        Self {
+            start,
+            end,
+            error: if end - start > 1 { error } else { 0.0 },
+            channel,
+        }
+    }
+}
+
+fn median_cut(histogram: &ColorHistogram, max_colors: usize) -> Vec<[u8; 4]> {
+    let mut entries = histogram.entries.clone();
+    let mut boxes = vec![ColorBox::new(&entries, 0, entries.len())];
+    while boxes.len() < max_colors {
+        let Some((index, _)) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.error > 0.0)
+            .max_by(|a, b| a.1.error.total_cmp(&b.1.error))
+        else {
+            break;
+        };
+        let target = boxes.swap_remove(index);
+        let slice = &mut entries[target.start..target.end];
+        let channel = target.channel;
+        slice.sort_unstable_by_key(|v| v.color[channel]);
+
+        // Split at the weighted median, keeping at least one entry on each side
+        let total = slice.iter().map(|v| v.count as u64).sum::<u64>();
+        let mut acc = 0;
+        let median = slice
+            .iter()
+            .position(|v| {
+                acc += v.count as u64;
+                acc * 2 >= total
+            })
+            .unwrap_or(0);
+        let mid = target.start + (median + 1).clamp(1, slice.len() - 1);
+        boxes.push(ColorBox::new(&entries, target.start, mid));
+        boxes.push(ColorBox::new(&entries, mid, target.end));
+    }
+    boxes
+        .iter()
+        .map(|v| mean_color(&entries[v.start..v.end]))
+        .collect()
+}
+
+/// Node of the octree, each level branches by one bit of each channel including alpha
+#[derive(Debug, Clone, Copy, Default)]
+struct OctreeNode {
+    children: [u32; 16],
+    sum: [u64; 4],
+    count: u64,
+    is_leaf: bool,
+}
+
+const OCTREE_DEPTH: usize = 8;
+
+fn octree(histogram: &ColorHistogram, max_colors: usize) -> Vec<[u8; 4]> {
+    let mut nodes = vec![OctreeNode::default()];
+    // Nodes with children of each level, the deepest ones are merged first
+    let mut reducible: [Vec<u32>; OCTREE_DEPTH] = Default::default();
+    let mut leaves = 0;
+
+    for entry in histogram.entries.iter() {
+        let mut index = 0;
+        for (level, reducible) in reducible.iter_mut().enumerate() {
+            if nodes[index].is_leaf {
+                break;
+            }
+            let shift = 7 - level;
+            let child = entry
+                .color
+                .iter()
+                .enumerate()
+                .fold(0, |acc, (ch, v)| acc | (((*v >> shift) & 1) as usize) << ch);
+            if nodes[index].children[child] == 0 {
+                if nodes[index].children == [0; 16] {
+                    reducible.push(index as u32);
+                }
+                nodes[index].children[child] = nodes.len() as u32;
+                nodes.push(OctreeNode::default());
+            }
+            index = nodes[index].children[child] as usize;
+        }
+        let node = &mut nodes[index];
+        if !node.is_leaf {
+            node.is_leaf = true;
+            leaves += 1;
+        }
+        for (acc, v) in node.sum.iter_mut().zip(entry.color) {
+            *acc += v as u64 * entry.count as u64;
+        }
+        node.count += entry.count as u64;
+
+        while leaves > max_colors {
+            let Some(level) = reducible.iter().rposition(|v| !v.is_empty()) else {
+                break;
+            };
+            let index = reducible[level].pop().unwrap() as usize;
+            leaves += 1;
+            for child in nodes[index].children {
+                if child == 0 {
+                    continue;
+                }
+                let child = core::mem::take(&mut nodes[child as usize]);
+                let node = &mut nodes[index];
+                for (acc, v) in node.sum.iter_mut().zip(child.sum) {
+                    *acc += v;
+                }
+                node.count += child.count;
+                leaves -= 1;
+            }
+            let node = &mut nodes[index];
+            node.children = [0; 16];
+            node.is_leaf = true;
+        }
+    }
+
+    nodes
+        .iter()
+        .filter(|v| v.is_leaf && v.count > 0)
+        .map(|v| v.sum.map(|s| ((s + v.count / 2) / v.count) as u8))
+        .collect()
+}
+
+/// Refine the palette by Lloyd's algorithm
+fn kmeans(histogram: &ColorHistogram, mut palette: Vec<[u8; 4]>) -> Vec<[u8; 4]> {
+    let entries = &histogram.entries;
+    let mut assignments = vec![usize::MAX; entries.len()];
+    for _ in 0..KMEANS_MAX_ITERATIONS {
+        let mut changed = false;
+        for (entry, assignment) in entries.iter().zip(assignments.iter_mut()) {
+            let nearest = nearest_color(&palette, entry.color);
+            if *assignment != nearest {
+                *assignment = nearest;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+
+        let mut sums = vec![([0u64; 4], 0u64); palette.len()];
+        for (entry, assignment) in entries.iter().zip(assignments.iter()) {
+            let (sum, count) = &mut sums[*assignment];
+            for (acc, v) in sum.iter_mut().zip(entry.color) {
+                *acc += v as u64 * entry.count as u64;
+            }
+            *count += entry.count as u64;
+        }
+        // Empty clusters keep their previous colors
+        for (color, (sum, count)) in palette.iter_mut().zip(sums) {
+            if count > 0 {
+                *color = sum.map(|v| ((v + count / 2) / count) as u8);
+            }
+        }
+    }
+    palette.sort_unstable();
+    palette.dedup();
+    palette
+}
@@ -0,0 +1,444 @@
+//! Resampling engine
+//!
+//! All scale modes share the same pixel-center convention: the center of the destination pixel `i`
+//! corresponds to the position `(i + 0.5) * src_len / dst_len` of the source.
+//! The image is resampled in two separable passes, so the horizontal and vertical modes can differ.
+
+use crate::{ImageInfo, MAGIC_NUMBER, ScaleMode, gamma::Transfer};
+use alloc::vec::Vec;
+use core::f64::consts::PI;
+
+/// Reconstruction filter for resampling
+#[derive(Debug, Clone, Copy)]
+pub struct ResampleFilter {
+    /// Radius of the filter at the original scale
+    pub support: f64,
+    pub kernel: fn(f64) -> f64,
+}
+
+impl ScaleMode {
+    /// Returns the convolution filter for this mode, `None` means point sampling
+    pub fn resample_filter(&self) -> Option<ResampleFilter> {
+        match self {
+            ScaleMode::Nearest => None,
+            ScaleMode::Bilinear => Some(ResampleFilter {
+                support: 1.0,
+                kernel: triangle,
+            }),
+            ScaleMode::Bicubic => Some(ResampleFilter {
+                support: 2.0,
+                kernel: catmull_rom,
+            }),
+            ScaleMode::Lanczos2 => Some(ResampleFilter {
+                support: 2.0,
+                kernel: lanczos2,
+            }),
+            ScaleMode::Lanczos3 => Some(ResampleFilter {
+                support: 3.0,
+                kernel: lanczos3,
+            }),
+            ScaleMode::Mitchell => Some(ResampleFilter {
+                support: 2.0,
+                kernel: mitchell,
+            }),
+            ScaleMode::CatmullRom => Some(ResampleFilter {
+                support: 2.0,
+                kernel: catmull_rom,
+            }),
+            ScaleMode::Hermite => Some(ResampleFilter {
+                support: 1.0,
+                kernel: hermite,
+            }),
+            ScaleMode::Gaussian => Some(ResampleFilter {
+                support: 2.0,
+                kernel: gaussian,
+            }),
+        }
+    }
+}
+
+fn triangle(x: f64) -> f64 {
+    let x = x.abs();
+    if x < 1.0 { 1.0 - x } else { 0.0 }
+}
+
+#[inline]
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = x * PI;
+        x.sin() / x
+    }
+}
+
+#[inline]
+fn lanczos(x: f64, a: f64) -> f64 {
+    if x.abs() < a {
+        sinc(x) * sinc(x / a)
+    } else {
+        0.0
+    }
+}
+
+fn lanczos2(x: f64) -> f64 {
+    lanczos(x, 2.0)
+}
+
+fn lanczos3(x: f64) -> f64 {
+    lanczos(x, 3.0)
+}
+
+/// Mitchell-Netravali family of cubic filters
+#[inline]
+fn bc_spline(x: f64, b: f64, c: f64) -> f64 {
+    let x = x.abs();
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b))
+            / 6.0
+    } else if x < 2.0 {
+        ((-b - 6.0 * c) * x * x * x
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        0.0
+    }
+}
+
+fn mitchell(x: f64) -> f64 {
+    bc_spline(x, 1.0 / 3.0, 1.0 / 3.0)
+}
+
+fn catmull_rom(x: f64) -> f64 {
+    bc_spline(x, 0.0, 0.5)
+}
+
+fn hermite(x: f64) -> f64 {
+    let x = x.abs();
+    if x < 1.0 {
+        (2.0 * x - 3.0) * x * x + 1.0
+    } else {
+        0.0
+    }
+}
+
+fn gaussian(x: f64) -> f64 {
+    (-2.0 * x * x).exp()
+}
+
+/// Weights of the source pixels that contribute to a destination pixel
+struct Contribution {
+    start: usize,
+    weights: Vec<f32>,
+}
+
+/// Compute the contributions for all destination pixels of a line
+fn contributions(src_len: u32, dst_len: u32, mode: ScaleMode) -> Vec<Contribution> {
+    let scale = src_len as f64 / dst_len as f64;
+    let max = src_len as isize - 1;
+
+    let filter = if src_len == dst_len {
+        // Same size is always an identity, even if the filter does not interpolate
+        None
+    } else {
+        mode.resample_filter()
+    };
+    let Some(filter) = filter else {
+        return (0..dst_len)
+            .map(|i| {
+                let center = (i as f64 + 0.5) * scale;
+                Contribution {
+                    start: (center.floor() as isize).clamp(0, max) as usize,
+                    weights: vec![1.0],
+                }
+            })
+            .collect();
+    };
+
+    // Widen the filter when reducing so that it also works as a low-pass filter
+    let filter_scale = scale.max(1.0);
+    let support = filter.support * filter_scale;
+
+    (0..dst_len)
+        .map(|i| {
+            let center = (i as f64 + 0.5) * scale;
+            let left = (center - support).floor() as isize;
+            let right = (center + support).ceil() as isize;
+
+            let mut weights = Vec::with_capacity((right - left) as usize);
+            let mut sum = 0.0;
+            for j in left..right {
+                let w = (filter.kernel)((j as f64 + 0.5 - center) / filter_scale);
+                weights.push(w);
+                sum += w;
+            }
+
+            // Pixels outside of the image are replaced with the pixels on the edge
+            let start = left.clamp(0, max) as usize;
+            let end = (right - 1).clamp(0, max) as usize;
+            let mut clamped = vec![0.0f32; end - start + 1];
+            for (j, w) in (left..right).zip(weights) {
+                let index = j.clamp(0, max) as usize - start;
+                clamped[index] += if sum != 0.0 { (w / sum) as f32 } else { 0.0 };
+            }
+
+            Contribution {
+                start,
+                weights: clamped,
+            }
+        })
+        .collect()
+}
+
+/// Resize a image in two separable passes
+///
+/// Pixels are processed in premultiplied alpha, and in linear light if enabled.
+pub fn resample(
+    info: &ImageInfo,
+    ib: &[u8],
+    ob: &mut Vec<u8>,
+    width: u32,
+    height: u32,
+    mode_x: ScaleMode,
+    mode_y: ScaleMode,
+) {
+    let sw = info.width as usize;
+    let sh = info.height as usize;
+
+    let transfer = Transfer::current();
+    let table: [f32; 256] = core::array::from_fn(|i| transfer.decode(i as u8, 0) as f32);
+
+    // Horizontal pass
+    let h_contributions = contributions(info.width, width, mode_x);
+    let mut temp = Vec::with_capacity(width as usize * sh * MAGIC_NUMBER);
+    for line in ib.chunks_exact(sw * MAGIC_NUMBER).take(sh) {
+        for contribution in h_contributions.iter() {
+            let mut acc = [0.0f32; 4];
+            for (pixel, w) in line[contribution.start * MAGIC_NUMBER..]
+                .chunks_exact(MAGIC_NUMBER)
+                .zip(contribution.weights.iter())
+            {
+                // Premultiplied alpha
+                let alpha = pixel[3] as f32;
+                let factor = alpha / 255.0 * w;
+                for ch in 0..3 {
+                    acc[ch] += table[pixel[ch] as usize] * factor;
+                }
+                acc[3] += alpha * w;
+            }
+            temp.extend_from_slice(&acc);
+        }
+    }
+
+    // Vertical pass
+    let stride = width as usize * MAGIC_NUMBER;
+    let v_contributions = contributions(info.height, height, mode_y);
+    let mut acc = vec![0.0f32; stride];
+    for contribution in v_contributions.iter() {
+        acc.fill(0.0);
+        for (line, w) in temp[contribution.start * stride..]
+            .chunks_exact(stride)
+            .zip(contribution.weights.iter())
+        {
+            for (acc, value) in acc.iter_mut().zip(line) {
+                *acc += value * w;
+            }
+        }
+        for value in acc.chunks_exact(MAGIC_NUMBER) {
+            let value = [
+                value[0] as f64,
+                value[1] as f64,
+                value[2] as f64,
+                value[3] as f64,
+            ];
+            ob.extend_from_slice(&transfer.encode_pixel(value));
+        }
+    }
+}
+
+/// Interpolation at the specified position of the image
+///
+/// The position is in the coordinates of the image, so the center of the pixel `(x, y)` is `(x + 0.5, y + 0.5)`.
+pub fn interpolate(info: &ImageInfo, ib: &[u8], vx: f64, vy: f64, mode: ScaleMode) -> [u8; 4] {
+    let max_x = info.width as f64 - 1.0;
+    let max_y = info.height as f64 - 1.0;
+
+    let Some(filter) = mode.resample_filter() else {
+        return info.get_pixel(
+            vx.floor().clamp(0.0, max_x) as u32,
+            vy.floor().clamp(0.0, max_y) as u32,
+            ib,
+        );
+    };
+
+    let vx = vx - 0.5;
+    let vy = vy - 0.5;
+    let left = (vx - filter.support).ceil();
+    let top = (vy - filter.support).ceil();
+    let size = filter.support as usize * 2;
+
+    let transfer = Transfer::current();
+    let mut acc = [0.0; 4];
+    let mut sum = 0.0;
+    for j in 0..size {
+        let y = top + j as f64;
+        let wy = (filter.kernel)(y - vy);
+        if wy == 0.0 {
+            continue;
+        }
+        for i in 0..size {
+            let x = left + i as f64;
+            let w = (filter.kernel)(x - vx) * wy;
+            if w == 0.0 {
+                continue;
+            }
+            let pixel = transfer.decode_pixel(info.get_pixel(
+                x.clamp(0.0, max_x) as u32,
+                y.clamp(0.0, max_y) as u32,
+                ib,
+            ));
+            for ch in 0..4 {
+                acc[ch] += pixel[ch] * w;
+            }
+            sum += w;
+        }
+    }
+
+    if sum != 0.0 {
+        for value in acc.iter_mut() {
+            *value /= sum;
+        }
+    }
+    transfer.encode_pixel(acc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Transparency, scale_buffer, scale_buffer_xy};
+
+    /// Opaque red square surrounded by transparent black
+    fn sprite(size: u32, border: u32) -> (ImageInfo, Vec<u8>) {
+        let info = ImageInfo::new(size, size, Transparency::Translucent);
+        let mut ib = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                let inside =
+                    (border..size - border).contains(&x) && (border..size - border).contains(&y);
+                ib.extend_from_slice(if inside {
+                    &[255, 0, 0, 255]
+                } else {
+                    &[0, 0, 0, 0]
+                });
+            }
+        }
+        (info, ib)
+    }
+
+    #[test]
+    fn no_halo_around_transparent_border() {
+        let (info, ib) = sprite(12, 3);
+        for mode in [ScaleMode::Bilinear, ScaleMode::Bicubic, ScaleMode::Lanczos3] {
+            for size in [5, 7, 25, 40] {
+                let ob = scale_buffer(&info, &ib, size, size, mode).unwrap();
+                let visible = ob.chunks_exact(MAGIC_NUMBER).filter(|v| v[3] > 0);
+                for pixel in visible {
+                    assert_eq!(pixel[..3], [255, 0, 0], "{mode:?} {size}: {pixel:?}");
+                }
+            }
+        }
+    }
+
+    /// Opaque image from the values of red, green and blue of each pixel
+    fn image(width: u32, height: u32, f: impl Fn(u32, u32) -> [u8; 3]) -> (ImageInfo, Vec<u8>) {
+        let info = ImageInfo::new(width, height, Transparency::Opaque);
+        let mut ib = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let [r, g, b] = f(x, y);
+                ib.extend_from_slice(&[r, g, b, 255]);
+            }
+        }
+        (info, ib)
+    }
+
+    fn channel(ob: &[u8], ch: usize) -> Vec<u8> {
+        ob.chunks_exact(MAGIC_NUMBER).map(|v| v[ch]).collect()
+    }
+
+    #[test]
+    fn pixel_centers_are_aligned() {
+        let (info, ib) = image(4, 1, |x, _| [x as u8 * 60; 3]);
+        let nearest = scale_buffer(&info, &ib, 8, 1, ScaleMode::Nearest).unwrap();
+        assert_eq!(channel(&nearest, 0), [0, 0, 60, 60, 120, 120, 180, 180]);
+        let bilinear = scale_buffer(&info, &ib, 8, 1, ScaleMode::Bilinear).unwrap();
+        assert_eq!(channel(&bilinear, 0), [0, 15, 45, 75, 105, 135, 165, 180]);
+        // Catmull-Rom reproduces a linear ramp where the taps do not reach the edges
+        let bicubic = channel(
+            &scale_buffer(&info, &ib, 8, 1, ScaleMode::Bicubic).unwrap(),
+            0,
+        );
+        assert_eq!(bicubic[3..5], [75, 105]);
+        assert!(bicubic.windows(2).all(|v| v[0] <= v[1]), "{bicubic:?}");
+    }
+
+    #[test]
+    fn same_size_is_identity() {
+        let (info, ib) = image(7, 5, |x, y| {
+            [(x * 37) as u8, (y * 51) as u8, (x * y * 11) as u8]
+        });
+        for mode in [
+            ScaleMode::Nearest,
+            ScaleMode::Bilinear,
+            ScaleMode::Bicubic,
+            ScaleMode::Lanczos2,
+            ScaleMode::Lanczos3,
+            ScaleMode::Mitchell,
+            ScaleMode::CatmullRom,
+            ScaleMode::Hermite,
+            ScaleMode::Gaussian,
+        ] {
+            assert_eq!(
+                scale_buffer(&info, &ib, 7, 5, mode).unwrap(),
+                ib,
+                "{mode:?}"
+            );
+            // Also without the shortcut of the same size
+            let mut ob = Vec::new();
+            resample(&info, &ib, &mut ob, 7, 5, mode, mode);
+            assert_eq!(ob, ib, "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn overshoot_is_clamped() {
+        let (info, ib) = image(8, 1, |x, _| [if x < 4 { 0 } else { 255 }; 3]);
+        for mode in [ScaleMode::Bicubic, ScaleMode::Lanczos2, ScaleMode::Lanczos3] {
+            let ob = channel(&scale_buffer(&info, &ib, 23, 1, mode).unwrap(), 0);
+            // Ringing must saturate instead of wrapping around
+            let (dark, bright) = ob.split_at(ob.len() / 2);
+            assert!(dark.iter().all(|v| *v < 128), "{mode:?} {ob:?}");
+            assert!(bright[1..].iter().all(|v| *v > 128), "{mode:?} {ob:?}");
+            assert_eq!((ob[0], ob[ob.len() - 1]), (0, 255), "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn independent_modes_for_each_direction() {
+        let (info, ib) = image(4, 4, |x, y| [x as u8 * 80, y as u8 * 80, 0]);
+        let ob =
+            scale_buffer_xy(&info, &ib, 8, 2, ScaleMode::Nearest, ScaleMode::Bilinear).unwrap();
+        let red = channel(&ob, 0);
+        let green = channel(&ob, 1);
+        for row in 0..2 {
+            assert_eq!(red[row * 8..][..8], [0, 0, 80, 80, 160, 160, 240, 240]);
+        }
+        // The widened triangle filter with the edges replicated
+        assert!(green[..8].iter().all(|v| *v == 50), "{green:?}");
+        assert!(green[8..].iter().all(|v| *v == 190), "{green:?}");
+    }
+}
@@ -0,0 +1,365 @@
+//! Histograms and statistics of the image
+
+use crate::quantize::{self, QuantizeAlgorithm};
+use crate::{MAGIC_NUMBER, image_buffer, luminance};
+use alloc::vec::Vec;
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+/// Channels of the histograms and the statistics, in the order of the returned arrays
+#[wasm_bindgen]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StatisticsChannel {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+    Luminance,
+}
+
+/// Statistics of each channel, in the order of the returned arrays
+#[wasm_bindgen]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Statistic {
+    Min,
+    Max,
+    Mean,
+    StdDev,
+    Median,
+}
+
+const NUMBER_OF_CHANNELS: usize = 5;
+const NUMBER_OF_STATISTICS: usize = 5;
+
+/// Histograms of 256 bins for each channel, concatenated in the order of `StatisticsChannel`
+///
+/// Pixels whose alpha is not greater than `alpha_threshold` are excluded from the color channels and the luminance,
+/// all pixels are counted if it is not specified.
+#[wasm_bindgen]
+pub fn image_histograms(alpha_threshold: Option<u8>) -> Vec<u32> {
+    Histograms::new(image_buffer(), alpha_threshold)
+        .channels
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Statistics of each channel, the value is at `channel * number_of_statistics() + statistic`
+///
+/// Pixels are excluded in the same way as `image_histograms`.
+/// All statistics of a channel are `NaN` if there are no pixels to count.
+#[wasm_bindgen]
+pub fn image_statistics(alpha_threshold: Option<u8>) -> Vec<f64> {
+    let histograms = Histograms::new(image_buffer(), alpha_threshold);
+    let mut result = Vec::with_capacity(NUMBER_OF_CHANNELS * NUMBER_OF_STATISTICS);
+    for histogram in histograms.channels.iter() {
+        result.extend_from_slice(&ChannelStatistics::new(histogram).to_array());
+    }
+    result
+}
+
+/// Number of the statistics of each channel returned by `image_statistics`
+#[wasm_bindgen]
+pub fn number_of_statistics() -> u32 {
+    NUMBER_OF_STATISTICS as u32
+}
+
+/// Number of fully transparent pixels, number of fully opaque pixels and the mean alpha (`0.0` to `1.0`)
+#[wasm_bindgen]
+pub fn alpha_coverage() -> Vec<f64> {
+    let ib = image_buffer();
+    let mut transparent = 0u64;
+    let mut opaque = 0u64;
+    let mut sum = 0u64;
+    for pixel in ib.chunks_exact(MAGIC_NUMBER) {
+        match pixel[3] {
+            0 => transparent += 1,
+            u8::MAX => opaque += 1,
+            _ => {}
+        }
+        sum += pixel[3] as u64;
+    }
+    let count = ib.len() / MAGIC_NUMBER;
+    let coverage = if count > 0 {
+        sum as f64 / (count as f64 * 255.0)
+    } else {
+        0.0
+    };
+    vec![transparent as f64, opaque as f64, coverage]
+}
+
+/// Number of distinct colors of the image
+///
+/// Fully transparent pixels are counted as one color regardless of their RGB.
+/// Returns `None` as soon as the number exceeds `limit`, if specified.
+#[wasm_bindgen]
+pub fn count_colors(limit: Option<u32>) -> Option<u32> {
+    let ib = image_buffer();
+    match limit {
+        Some(limit) => Some(color_counts(ib, limit as usize)?.len() as u32),
+        None => {
+            let mut colors = Vec::new();
+            colors.try_reserve(ib.len() / MAGIC_NUMBER).ok()?;
+            colors.extend(ib.chunks_exact(MAGIC_NUMBER).map(color_key));
+            colors.sort_unstable();
+            colors.dedup();
+            Some(colors.len() as u32)
+        }
+    }
+}
+
+/// Distinct colors of the image and the number of pixels of each, up to `max` colors
+///
+/// Returns pairs of the color in `0xRRGGBBAA` and the count in descending order of the count,
+/// or `None` if the image has more than `max` colors.
+#[wasm_bindgen]
+pub fn extract_palette(max: u32) -> Option<Vec<u32>> {
+    let counts = color_counts(image_buffer(), max as usize)?;
+    Some(sort_by_count(counts.into_iter().collect()))
+}
+
+/// Dominant colors of the image found by clustering, up to `max` (`1` to `256`) colors
+///
+/// Returns pairs of the color in `0xRRGGBBAA` and the number of pixels nearest to it in descending order of the count.
+/// Fully transparent pixels are ignored.
+#[wasm_bindgen]
+pub fn dominant_colors(max: u32) -> Option<Vec<u32>> {
+    dominant_colors_buffer(image_buffer(), max)
+}
+
+pub fn dominant_colors_buffer(ib: &[u8], max: u32) -> Option<Vec<u32>> {
+    let palette = quantize::build_visible_palette(ib, max, QuantizeAlgorithm::KMeans)?;
+    let mut counts = vec![0u32; palette.len()];
+    let mut cache = HashMap::new();
+    for pixel in ib.chunks_exact(MAGIC_NUMBER).filter(|v| v[3] > 0) {
+        let index = *cache.entry(color_key(pixel)).or_insert_with(|| {
+            quantize::nearest_color(&palette, quantize::normalize(pixel.try_into().unwrap()))
+        });
+        counts[index] += 1;
+    }
+    Some(sort_by_count(
+        palette
+            .iter()
+            .zip(counts)
+            .filter(|(_, count)| *count > 0)
+            .map(|(color, count)| (u32::from_be_bytes(*color), count))
+            .collect(),
+    ))
+}
+
+/// Fully transparent pixels have the same key
+#[inline]
+fn color_key(pixel: &[u8]) -> u32 {
+    if pixel[3] == 0 {
+        0
+    } else {
+        u32::from_be_bytes(pixel.try_into().unwrap())
+    }
+}
+
+fn color_counts(ib: &[u8], max: usize) -> Option<HashMap<u32, u32>> {
+    let mut counts = HashMap::new();
+    for pixel in ib.chunks_exact(MAGIC_NUMBER) {
+        *counts.entry(color_key(pixel)).or_insert(0) += 1;
+        if counts.len() > max {
+            return None;
+        }
+    }
+    Some(counts)
+}
+
+fn sort_by_count(mut counts: Vec<(u32, u32)>) -> Vec<u32> {
+    counts.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts
+        .into_iter()
+        .flat_map(|(color, count)| [color, count])
+        .collect()
+}
+
+/// Histograms of all channels
+#[derive(Debug, Clone)]
+pub struct Histograms {
+    pub channels: [[u32; 256]; NUMBER_OF_CHANNELS],
+}
+
+impl Histograms {
+    /// Pixels whose alpha is not greater than `alpha_threshold` are counted only in the alpha channel
+    pub fn new(ib: &[u8], alpha_threshold: Option<u8>) -> Self {
+        let mut channels = [[0u32; 256]; NUMBER_OF_CHANNELS];
+        for pixel in ib.chunks_exact(MAGIC_NUMBER) {
+            channels[StatisticsChannel::Alpha as usize][pixel[3] as usize] += 1;
+            if alpha_threshold.is_some_and(|v| pixel[3] <= v) {
+                continue;
+            }
+            for ch in 0..3 {
+                channels[ch][pixel[ch] as usize] += 1;
+            }
+            channels[StatisticsChannel::Luminance as usize][luminance(pixel) as usize] += 1;
+        }
+        Self { channels }
+    }
+
+    #[inline]
+    pub fn channel(&self, channel: StatisticsChannel) -> &[u32; 256] {
+        &self.channels[channel as usize]
+    }
+}
+
+/// Statistics of a histogram
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelStatistics {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub median: f64,
+}
+
+impl ChannelStatistics {
+    pub fn new(histogram: &[u32; 256]) -> Self {
+        let total = histogram.iter().map(|v| *v as u64).sum::<u64>();
+        if total == 0 {
+            return Self {
+                min: f64::NAN,
+                max: f64::NAN,
+                mean: f64::NAN,
+                std_dev: f64::NAN,
+                median: f64::NAN,
+            };
+        }
+        let min = histogram.iter().position(|v| *v > 0).unwrap_or(0);
+        let max = histogram.iter().rposition(|v| *v > 0).unwrap_or(0);
+        let (sum, sum_sq) =
+            histogram
+                .iter()
+                .enumerate()
+                .fold((0.0, 0.0), |(sum, sum_sq), (i, v)| {
+                    let v = *v as f64;
+                    let i = i as f64;
+                    (sum + i * v, sum_sq + i * i * v)
+                });
+        let mean = sum / total as f64;
+        let variance = (sum_sq / total as f64 - mean * mean).max(0.0);
+        Self {
+            min: min as f64,
+            max: max as f64,
+            mean,
+            std_dev: variance.sqrt(),
+            median: percentile(histogram, 0.5) as f64,
+        }
+    }
+
+    #[inline]
+    pub fn to_array(&self) -> [f64; NUMBER_OF_STATISTICS] {
+        [self.min, self.max, self.mean, self.std_dev, self.median]
+    }
+}
+
+/// The smallest value where the cumulative count reaches `ratio` (`0.0` to `1.0`) of the total
+pub fn percentile(histogram: &[u32; 256], ratio: f64) -> u8 {
+    let total = histogram.iter().map(|v| *v as u64).sum::<u64>();
+    let target = (total as f64 * ratio.clamp(0.0, 1.0)).ceil().max(1.0) as u64;
+    let mut acc = 0;
+    histogram
+        .iter()
+        .position(|v| {
+            acc += *v as u64;
+            acc >= target
+        })
+        .unwrap_or(255) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Histogram of the values and the number of each
+    fn histogram(values: &[(u8, u32)]) -> [u32; 256] {
+        let mut histogram = [0; 256];
+        for &(value, count) in values {
+            histogram[value as usize] = count;
+        }
+        histogram
+    }
+
+    #[test]
+    fn statistics_of_known_histogram() {
+        // 10, 10, 20, 30
+        let statistics = ChannelStatistics::new(&histogram(&[(10, 2), (20, 1), (30, 1)]));
+        assert_eq!((statistics.min, statistics.max), (10.0, 30.0));
+        assert_eq!(statistics.mean, 17.5);
+        assert!((statistics.std_dev - 68.75f64.sqrt()).abs() < 1e-9);
+        assert_eq!(statistics.median, 10.0);
+
+        let statistics = ChannelStatistics::new(&histogram(&[(0, 1), (128, 3), (255, 1)]));
+        assert_eq!(
+            statistics.to_array()[Statistic::Median as usize],
+            statistics.median
+        );
+        assert_eq!(statistics.median, 128.0);
+
+        let statistics = ChannelStatistics::new(&histogram(&[(77, 1000)]));
+        assert_eq!(statistics.to_array(), [77.0, 77.0, 77.0, 0.0, 77.0]);
+    }
+
+    #[test]
+    fn statistics_of_empty_histogram() {
+        let statistics = ChannelStatistics::new(&[0; 256]);
+        assert!(statistics.to_array().iter().all(|v| v.is_nan()));
+    }
+
+    #[test]
+    fn percentiles() {
+        let histogram = histogram(&[(10, 2), (20, 1), (30, 1)]);
+        assert_eq!(percentile(&histogram, 0.0), 10);
+        assert_eq!(percentile(&histogram, 0.5), 10);
+        assert_eq!(percentile(&histogram, 0.51), 20);
+        assert_eq!(percentile(&histogram, 0.75), 20);
+        assert_eq!(percentile(&histogram, 1.0), 30);
+        assert_eq!(percentile(&histogram, 2.0), 30);
+    }
+
+    #[test]
+    fn histograms_exclude_transparent_pixels() {
+        let ib = [10, 20, 30, 255, 200, 200, 200, 0, 40, 50, 60, 100];
+        let histograms = Histograms::new(&ib, Some(0));
+        assert_eq!(
+            histograms
+                .channel(StatisticsChannel::Red)
+                .iter()
+                .sum::<u32>(),
+            2
+        );
+        assert_eq!(histograms.channel(StatisticsChannel::Red)[200], 0);
+        assert_eq!(histograms.channel(StatisticsChannel::Alpha)[0], 1);
+        let histograms = Histograms::new(&ib, None);
+        assert_eq!(histograms.channel(StatisticsChannel::Red)[200], 1);
+        let histograms = Histograms::new(&ib, Some(100));
+        assert_eq!(
+            histograms
+                .channel(StatisticsChannel::Luminance)
+                .iter()
+                .sum::<u32>(),
+            1
+        );
+    }
+
+    #[test]
+    fn dominant_colors_ignore_transparent_pixels() {
+        let mut ib = [200, 30, 30, 255].repeat(90);
+        ib.extend_from_slice(&[30, 30, 200, 255].repeat(9));
+        ib.extend_from_slice(&[0; 4]);
+        // The mean of the visible pixels
+        assert_eq!(dominant_colors_buffer(&ib, 1).unwrap(), [0xb9_1e_2d_ff, 99]);
+
+        let colors = dominant_colors_buffer(&ib, 2).unwrap();
+        assert_eq!(colors, [0xc8_1e_1e_ff, 90, 0x1e_1e_c8_ff, 9]);
+
+        // Transparent pixels are not counted even if they are the majority
+        ib.extend_from_slice(&[0; 4].repeat(200));
+        assert_eq!(dominant_colors_buffer(&ib, 2).unwrap(), colors);
+        assert_eq!(dominant_colors_buffer(&[0; 16], 4).unwrap(), []);
+    }
+}
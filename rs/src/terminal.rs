@@ -0,0 +1,194 @@
+//! Preview the image on a terminal
+
+use crate::{
+    ImageInfo, ScaleMode, dither::DitherMode, image_buffer, image_info, posterize_buffer,
+    scale_buffer,
+};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use wasm_bindgen::prelude::*;
+
+/// Pixels with an alpha value below this are not drawn
+const ALPHA_THRESHOLD: u8 = 0x80;
+
+/// Number of levels of each channel of the sixel palette, 6 * 7 * 6 = 252 colors fit in 256 color registers
+const SIXEL_LEVELS: [u8; 3] = [6, 7, 6];
+
+/// Render the current image with ANSI 24-bit color and half-block characters
+///
+/// Each character cell represents two vertically arranged pixels, so the image is scaled to `columns` pixels wide.
+#[wasm_bindgen]
+pub fn render_ansi(columns: u32, mode: ScaleMode) -> Option<String> {
+    let (info, ib) = scaled_image(columns, mode)?;
+
+    let mut s = String::new();
+    for y in (0..info.height).step_by(2) {
+        for x in 0..info.width {
+            let upper = info.get_pixel(x, y, &ib);
+            let lower = if y + 1 < info.height {
+                info.get_pixel(x, y + 1, &ib)
+            } else {
+                [0; 4]
+            };
+            match (upper[3] >= ALPHA_THRESHOLD, lower[3] >= ALPHA_THRESHOLD) {
+                (true, true) => {
+                    let _ = write!(
+                        s,
+                        "\x1b[38;2;{};{};{};48;2;{};{};{}m\u{2580}",
+                        upper[0], upper[1], upper[2], lower[0], lower[1], lower[2],
+                    );
+                }
+                (true, false) => {
+                    let _ = write!(
+                        s,
+                        "\x1b[0;38;2;{};{};{}m\u{2580}",
+                        upper[0], upper[1], upper[2],
+                    );
+                }
+                (false, true) => {
+                    let _ = write!(
+                        s,
+                        "\x1b[0;38;2;{};{};{}m\u{2584}",
+                        lower[0], lower[1], lower[2],
+                    );
+                }
+                (false, false) => {
+                    s.push_str("\x1b[0m ");
+                }
+            }
+        }
+        s.push_str("\x1b[0m\n");
+    }
+
+    Some(s)
+}
+
+/// Render the current image as DEC Sixel graphics
+///
+/// The image is scaled to `columns * cell_width` pixels wide and its colors are reduced with `posterize`.
+#[wasm_bindgen]
+pub fn render_sixel(
+    columns: u32,
+    cell_width: u32,
+    mode: ScaleMode,
+    dither: DitherMode,
+) -> Option<String> {
+    let (info, mut ib) = scaled_image(columns.checked_mul(cell_width)?, mode)?;
+    let [red, green, blue] = SIXEL_LEVELS;
+    if !posterize_buffer(&info, &mut ib, dither, false, [red, green, blue, 0]) {
+        return None;
+    }
+
+    // Color register of each pixel, `None` means transparent
+    let registers = ib
+        .chunks_exact(4)
+        .map(|rgba| {
+            (rgba[3] >= ALPHA_THRESHOLD).then(|| {
+                let r = level(rgba[0], red) as usize;
+                let g = level(rgba[1], green) as usize;
+                let b = level(rgba[2], blue) as usize;
+                (r * green as usize + g) * blue as usize + b
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut s = String::new();
+    // P2 = 1: pixels without color remain transparent
+    let _ = write!(s, "\x1bP0;1;0q\"1;1;{};{}", info.width, info.height);
+
+    let mut used = [false; 256];
+    for register in registers.iter().flatten() {
+        used[*register] = true;
+    }
+    for (register, _) in used.iter().enumerate().filter(|v| *v.1) {
+        let b = register % blue as usize;
+        let g = register / blue as usize % green as usize;
+        let r = register / blue as usize / green as usize;
+        let _ = write!(
+            s,
+            "#{register};2;{};{};{}",
+            percent(r, red),
+            percent(g, green),
+            percent(b, blue),
+        );
+    }
+
+    let width = info.width as usize;
+    let mut sixels = Vec::with_capacity(width);
+    for band in registers.chunks(width * 6) {
+        let mut colors = [false; 256];
+        for register in band.iter().flatten() {
+            colors[*register] = true;
+        }
+        let mut is_first = true;
+        for (register, _) in colors.iter().enumerate().filter(|v| *v.1) {
+            sixels.clear();
+            sixels.resize(width, 0u8);
+            for (row, line) in band.chunks_exact(width).enumerate() {
+                for (sixel, pixel) in sixels.iter_mut().zip(line) {
+                    if *pixel == Some(register) {
+                        *sixel |= 1 << row;
+                    }
+                }
+            }
+            while sixels.last() == Some(&0) {
+                sixels.pop();
+            }
+
+            if !is_first {
+                s.push('$');
+            }
+            is_first = false;
+            let _ = write!(s, "#{register}");
+            write_sixels(&mut s, &sixels);
+        }
+        s.push('-');
+    }
+    s.push_str("\x1b\\");
+
+    Some(s)
+}
+
+/// Scale the current image to the specified width, keeping the aspect ratio
+fn scaled_image(width: u32, mode: ScaleMode) -> Option<(ImageInfo, Vec<u8>)> {
+    let info = image_info();
+    if width < 1 || info.width < 1 || info.height < 1 {
+        return None;
+    }
+    let height = ((info.height as u64 * width as u64 + info.width as u64 / 2) / info.width as u64)
+        .clamp(1, u32::MAX as u64) as u32;
+    let ib = scale_buffer(info, image_buffer(), width, height, mode)?;
+    Some((ImageInfo::new(width, height, info.transparency), ib))
+}
+
+/// Write sixel characters with repeat introducers
+fn write_sixels(s: &mut String, sixels: &[u8]) {
+    let mut iter = sixels.iter().peekable();
+    while let Some(&sixel) = iter.next() {
+        let mut count = 1;
+        while iter.next_if_eq(&&sixel).is_some() {
+            count += 1;
+        }
+        let ch = (0x3F + sixel) as char;
+        if count > 3 {
+            let _ = write!(s, "!{count}{ch}");
+        } else {
+            for _ in 0..count {
+                s.push(ch);
+            }
+        }
+    }
+}
+
+/// Index of the level of a posterized channel value
+#[inline]
+fn level(value: u8, levels: u8) -> u32 {
+    let max = levels as u32 - 1;
+    (value as u32 * max + 127) / 255
+}
+
+/// Sixel color components are specified in percent
+#[inline]
+fn percent(level: usize, levels: u8) -> usize {
+    (level * 100 + (levels as usize - 1) / 2) / (levels as usize - 1)
+}
@@ -0,0 +1,351 @@
+//! Tonal adjustments
+//!
+//! All adjustments are implemented as lookup tables of 256 entries applied to the color channels.
+
+use crate::stats::{ChannelStatistics, Histograms, StatisticsChannel, percentile};
+use crate::{MAGIC_NUMBER, image_buffer, image_info};
+use alloc::vec::Vec;
+use wasm_bindgen::prelude::*;
+
+/// Ratio of the brightest pixels ignored by the white patch method
+const WHITE_PATCH_CLIP: f64 = 0.01;
+
+/// Channels to which the adjustments are applied
+#[wasm_bindgen]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ToneChannel {
+    /// All color channels
+    Composite,
+    Red,
+    Green,
+    Blue,
+}
+
+impl ToneChannel {
+    /// Indices of the channels of a pixel
+    #[inline]
+    pub fn indices(&self) -> &'static [usize] {
+        match self {
+            ToneChannel::Composite => &[0, 1, 2],
+            ToneChannel::Red => &[0],
+            ToneChannel::Green => &[1],
+            ToneChannel::Blue => &[2],
+        }
+    }
+}
+
+/// Adjust brightness and contrast (both `-1.0` to `1.0`)
+#[wasm_bindgen]
+pub fn brightness_contrast(brightness: f64, contrast: f64) -> bool {
+    let Some(table) = brightness_contrast_table(brightness, contrast) else {
+        return false;
+    };
+    apply_table(ToneChannel::Composite, &table)
+}
+
+/// Apply gamma correction, values greater than `1.0` brighten the image
+#[wasm_bindgen]
+pub fn adjust_gamma(value: f64, channel: ToneChannel) -> bool {
+    let Some(table) = gamma_table(value) else {
+        return false;
+    };
+    apply_table(channel, &table)
+}
+
+/// Map the input range `in_black..=in_white` to the output range `out_black..=out_white`
+///
+/// `midtone` is the gamma applied between them, `1.0` is linear.
+#[wasm_bindgen]
+pub fn levels(
+    channel: ToneChannel,
+    in_black: u8,
+    in_white: u8,
+    midtone: f64,
+    out_black: u8,
+    out_white: u8,
+) -> bool {
+    let Some(table) = levels_table(in_black, in_white, midtone, out_black, out_white) else {
+        return false;
+    };
+    apply_table(channel, &table)
+}
+
+/// Apply a tone curve through the control points
+///
+/// `points` is a flat array of the pairs of input and output values.
+/// The curve is interpolated with a monotone cubic spline, so it never overshoots between the points.
+#[wasm_bindgen]
+pub fn curves(channel: ToneChannel, points: &[u8]) -> bool {
+    let Some(table) = curves_table(points) else {
+        return false;
+    };
+    apply_table(channel, &table)
+}
+
+/// Methods to estimate the color of the light
+#[wasm_bindgen]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WhiteBalanceMode {
+    /// The average color of the image is assumed to be gray
+    GrayWorld,
+    /// The brightest color of the image is assumed to be white
+    WhitePatch,
+}
+
+/// Stretch each color channel to the full range
+///
+/// `clip` is the percentage (`0.0` to `50.0`) of the pixels clipped to black and white in each channel.
+#[wasm_bindgen]
+pub fn auto_levels(clip: f64) -> bool {
+    let Some(clip) = clip_ratio(clip) else {
+        return false;
+    };
+    let histograms = Histograms::new(image_buffer(), Some(0));
+    for (channel, tone_channel) in [
+        (StatisticsChannel::Red, ToneChannel::Red),
+        (StatisticsChannel::Green, ToneChannel::Green),
+        (StatisticsChannel::Blue, ToneChannel::Blue),
+    ] {
+        if let Some(table) = stretch_table(histograms.channel(channel), clip) {
+            apply_table(tone_channel, &table);
+        }
+    }
+    true
+}
+
+/// Stretch the luminance to the full range
+///
+/// The same mapping is applied to all color channels, so the balance of the colors does not change.
+/// `clip` is the percentage (`0.0` to `50.0`) of the pixels clipped to black and white.
+#[wasm_bindgen]
+pub fn auto_contrast(clip: f64) -> bool {
+    let Some(clip) = clip_ratio(clip) else {
+        return false;
+    };
+    let histograms = Histograms::new(image_buffer(), Some(0));
+    if let Some(table) = stretch_table(histograms.channel(StatisticsChannel::Luminance), clip) {
+        apply_table(ToneChannel::Composite, &table);
+    }
+    true
+}
+
+/// Remove the color cast of the light
+#[wasm_bindgen]
+pub fn auto_white_balance(mode: WhiteBalanceMode) -> bool {
+    let histograms = Histograms::new(image_buffer(), Some(0));
+    let channels = [
+        StatisticsChannel::Red,
+        StatisticsChannel::Green,
+        StatisticsChannel::Blue,
+    ];
+    // The reference value of each channel is scaled to the target
+    let (references, target) = match mode {
+        WhiteBalanceMode::GrayWorld => {
+            let means = channels.map(|v| ChannelStatistics::new(histograms.channel(v)).mean);
+            (means, means.iter().sum::<f64>() / 3.0)
+        }
+        WhiteBalanceMode::WhitePatch => (
+            channels.map(|v| percentile(histograms.channel(v), 1.0 - WHITE_PATCH_CLIP) as f64),
+            255.0,
+        ),
+    };
+    if references.iter().any(|v| !(v.is_finite() && *v > 0.0)) {
+        return false;
+    }
+    for (reference, tone_channel) in
+        references
+            .into_iter()
+            .zip([ToneChannel::Red, ToneChannel::Green, ToneChannel::Blue])
+    {
+        let gain = target / reference;
+        apply_table(tone_channel, &make_tone_table(|v| v * gain));
+    }
+    true
+}
+
+/// Convert the percentage of clipping to the ratio
+#[inline]
+fn clip_ratio(clip: f64) -> Option<f64> {
+    (0.0..=50.0).contains(&clip).then_some(clip / 100.0)
+}
+
+/// Lookup table that stretches the range of the histogram to the full range
+///
+/// Returns `None` if the histogram is empty or has only one level.
+pub fn stretch_table(histogram: &[u32; 256], clip: f64) -> Option<[u8; 256]> {
+    if histogram.iter().all(|v| *v == 0) {
+        return None;
+    }
+    let low = percentile(histogram, clip);
+    let high = percentile(histogram, 1.0 - clip);
+    levels_table(low, high, 1.0, 0, 255)
+}
+
+/// Apply a lookup table to the current image
+pub fn apply_table(channel: ToneChannel, table: &[u8; 256]) -> bool {
+    let info = image_info();
+    if channel != ToneChannel::Composite {
+        info.is_grayscale = false;
+    }
+    apply_table_buffer(image_buffer(), channel, table);
+    true
+}
+
+/// Apply a lookup table to the specified buffer
+pub fn apply_table_buffer(ib: &mut [u8], channel: ToneChannel, table: &[u8; 256]) {
+    let indices = channel.indices();
+    for pixel in ib.chunks_exact_mut(MAGIC_NUMBER) {
+        for &ch in indices {
+            pixel[ch] = table[pixel[ch] as usize];
+        }
+    }
+}
+
+/// Make a lookup table from a function that maps `0.0..=1.0` to `0.0..=1.0`
+pub fn make_tone_table<F: Fn(f64) -> f64>(f: F) -> [u8; 256] {
+    let mut table = [0u8; 256];
+    for (i, value) in table.iter_mut().enumerate() {
+        *value = (f(i as f64 / 255.0) * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+    table
+}
+
+pub fn brightness_contrast_table(brightness: f64, contrast: f64) -> Option<[u8; 256]> {
+    if !(-1.0..=1.0).contains(&brightness) || !(-1.0..=1.0).contains(&contrast) {
+        return None;
+    }
+    // The slope of contrast is 0 at -1.0 and infinite at 1.0
+    let slope = ((contrast + 1.0) * core::f64::consts::FRAC_PI_4).tan();
+    Some(make_tone_table(|v| {
+        let v = if brightness < 0.0 {
+            v * (1.0 + brightness)
+        } else {
+            v + (1.0 - v) * brightness
+        };
+        if contrast >= 1.0 {
+            if v < 0.5 { 0.0 } else { 1.0 }
+        } else {
+            (v - 0.5) * slope + 0.5
+        }
+    }))
+}
+
+pub fn gamma_table(value: f64) -> Option<[u8; 256]> {
+    if !(value.is_finite() && value > 0.0) {
+        return None;
+    }
+    Some(make_tone_table(|v| v.powf(1.0 / value)))
+}
+
+pub fn levels_table(
+    in_black: u8,
+    in_white: u8,
+    midtone: f64,
+    out_black: u8,
+    out_white: u8,
+) -> Option<[u8; 256]> {
+    if in_black >= in_white || !(midtone.is_finite() && midtone > 0.0) {
+        return None;
+    }
+    let in_black = in_black as f64 / 255.0;
+    let in_white = in_white as f64 / 255.0;
+    let out_black = out_black as f64 / 255.0;
+    let out_white = out_white as f64 / 255.0;
+    Some(make_tone_table(|v| {
+        let v = ((v - in_black) / (in_white - in_black)).clamp(0.0, 1.0);
+        out_black + v.powf(1.0 / midtone) * (out_white - out_black)
+    }))
+}
+
+pub fn curves_table(points: &[u8]) -> Option<[u8; 256]> {
+    if !points.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut points = points
+        .chunks_exact(2)
+        .map(|v| (v[0] as f64, v[1] as f64))
+        .collect::<Vec<_>>();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    points.dedup_by(|a, b| a.0 == b.0);
+    let spline = MonotoneSpline::new(&points)?;
+
+    let mut table = [0u8; 256];
+    for (i, value) in table.iter_mut().enumerate() {
+        *value = spline.eval(i as f64).round().clamp(0.0, 255.0) as u8;
+    }
+    Some(table)
+}
+
+/// Monotone cubic interpolation (Fritsch-Carlson)
+struct MonotoneSpline<'a> {
+    points: &'a [(f64, f64)],
+    tangents: Vec<f64>,
+}
+
+impl<'a> MonotoneSpline<'a> {
+    /// The points must be sorted by x without duplicates
+    fn new(points: &'a [(f64, f64)]) -> Option<Self> {
+        let n = points.len();
+        if n < 2 {
+            return None;
+        }
+        let secants = points
+            .windows(2)
+            .map(|v| (v[1].1 - v[0].1) / (v[1].0 - v[0].0))
+            .collect::<Vec<_>>();
+
+        let mut tangents = Vec::with_capacity(n);
+        tangents.push(secants[0]);
+        for v in secants.windows(2) {
+            tangents.push(if v[0] * v[1] <= 0.0 {
+                0.0
+            } else {
+                (v[0] + v[1]) / 2.0
+            });
+        }
+        tangents.push(secants[n - 2]);
+
+        // Limit the tangents so that each segment stays monotonic
+        for (i, secant) in secants.iter().enumerate() {
+            if *secant == 0.0 {
+                tangents[i] = 0.0;
+                tangents[i + 1] = 0.0;
+                continue;
+            }
+            let a = tangents[i] / secant;
+            let b = tangents[i + 1] / secant;
+            let h = a * a + b * b;
+            if h > 9.0 {
+                let t = 3.0 / h.sqrt();
+                tangents[i] = t * a * secant;
+                tangents[i + 1] = t * b * secant;
+            }
+        }
+
+        Some(Self { points, tangents })
+    }
+
+    /// Values outside of the control points are extended flat
+    fn eval(&self, x: f64) -> f64 {
+        let points = self.points;
+        let last = points.len() - 1;
+        if x <= points[0].0 {
+            return points[0].1;
+        } else if x >= points[last].0 {
+            return points[last].1;
+        }
+        let i = points.partition_point(|v| v.0 <= x) - 1;
+        let (x0, y0) = points[i];
+        let (x1, y1) = points[i + 1];
+        let h = x1 - x0;
+        let t = (x - x0) / h;
+        let t2 = t * t;
+        let t3 = t2 * t;
+        (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+            + (t3 - 2.0 * t2 + t) * h * self.tangents[i]
+            + (-2.0 * t3 + 3.0 * t2) * y1
+            + (t3 - t2) * h * self.tangents[i + 1]
+    }
+}
@@ -0,0 +1,261 @@
+//! Geometric transforms
+
+use crate::{
+    _set_image_buffer, ImageInfo, MAGIC_NUMBER, ScaleMode, blend, image_buffer, image_info,
+    resample::interpolate,
+};
+use alloc::vec::Vec;
+use wasm_bindgen::prelude::*;
+
+/// Rotate the image 90 degrees clockwise
+#[wasm_bindgen]
+pub fn rotate90() {
+    let info = image_info();
+    transpose_buffer(info, image_buffer());
+    flip_horizontal_buffer(info, image_buffer());
+}
+
+/// Rotate the image 180 degrees
+#[wasm_bindgen]
+pub fn rotate180() {
+    rotate180_buffer(image_buffer());
+}
+
+/// Rotate the image 270 degrees clockwise (90 degrees counterclockwise)
+#[wasm_bindgen]
+pub fn rotate270() {
+    let info = image_info();
+    transpose_buffer(info, image_buffer());
+    flip_vertical_buffer(info, image_buffer());
+}
+
+#[wasm_bindgen]
+pub fn flip_horizontal() {
+    flip_horizontal_buffer(image_info(), image_buffer());
+}
+
+#[wasm_bindgen]
+pub fn flip_vertical() {
+    flip_vertical_buffer(image_info(), image_buffer());
+}
+
+/// Swap the rows and columns of the image, mirroring it along the main diagonal
+#[wasm_bindgen]
+pub fn transpose() {
+    transpose_buffer(image_info(), image_buffer());
+}
+
+/// Rotate the image clockwise by an arbitrary angle
+///
+/// If `expand` is specified, the canvas is enlarged to fit the rotated image, otherwise the size is kept.
+/// The uncovered area is filled with `background` (`0xRRGGBBAA`).
+#[wasm_bindgen]
+pub fn rotate(angle_degrees: f64, mode: ScaleMode, expand: bool, background: u32) -> bool {
+    let info = image_info();
+    if !angle_degrees.is_finite() {
+        return false;
+    }
+
+    // Right angles can be done losslessly
+    let angle = angle_degrees.rem_euclid(360.0);
+    if expand || info.width == info.height {
+        if angle == 0.0 {
+            return true;
+        } else if angle == 90.0 {
+            rotate90();
+            return true;
+        } else if angle == 180.0 {
+            rotate180();
+            return true;
+        } else if angle == 270.0 {
+            rotate270();
+            return true;
+        }
+    }
+
+    let Some((new_info, ob)) = rotate_buffer(
+        info,
+        image_buffer(),
+        angle_degrees,
+        mode,
+        expand,
+        background.to_be_bytes(),
+    ) else {
+        return false;
+    };
+
+    _set_image_buffer(ob.as_slice(), new_info.width, new_info.height)
+}
+
+/// Rotate the buffer clockwise by an arbitrary angle into a new buffer
+pub fn rotate_buffer(
+    info: &ImageInfo,
+    ib: &[u8],
+    angle_degrees: f64,
+    mode: ScaleMode,
+    expand: bool,
+    background: [u8; 4],
+) -> Option<(ImageInfo, Vec<u8>)> {
+    let sw = info.width as f64;
+    let sh = info.height as f64;
+    let (sin, cos) = angle_degrees.to_radians().sin_cos();
+
+    let (width, height) = if expand {
+        // Small errors of the trigonometric functions must not add an extra line
+        const EPSILON: f64 = 1.0e-6;
+        let dw = sw * cos.abs() + sh * sin.abs();
+        let dh = sw * sin.abs() + sh * cos.abs();
+        ((dw - EPSILON).ceil() as u32, (dh - EPSILON).ceil() as u32)
+    } else {
+        (info.width, info.height)
+    };
+    if width < 1 || height < 1 {
+        return None;
+    }
+
+    let mut ob = Vec::new();
+    if ob
+        .try_reserve(width as usize * height as usize * MAGIC_NUMBER)
+        .is_err()
+    {
+        return None;
+    }
+
+    let dcx = width as f64 / 2.0;
+    let dcy = height as f64 / 2.0;
+    let scx = sw / 2.0;
+    let scy = sh / 2.0;
+    for y in 0..height {
+        let dy = y as f64 + 0.5 - dcy;
+        for x in 0..width {
+            let dx = x as f64 + 0.5 - dcx;
+            // Inverse mapping from the destination to the source
+            let vx = dx * cos + dy * sin + scx;
+            let vy = -dx * sin + dy * cos + scy;
+
+            // Coverage of the pixel to smooth the edges of the rotated image
+            let coverage = (vx + 0.5)
+                .min(sw - vx + 0.5)
+                .min(vy + 0.5)
+                .min(sh - vy + 0.5)
+                .clamp(0.0, 1.0);
+            let new_pixel = if coverage <= 0.0 {
+                background
+            } else {
+                let mut pixel = if mode == ScaleMode::Nearest
+                    && (vx < 0.0 || vx >= sw || vy < 0.0 || vy >= sh)
+                {
+                    background
+                } else {
+                    interpolate(info, ib, vx, vy, mode)
+                };
+                if mode != ScaleMode::Nearest && coverage < 1.0 {
+                    pixel[3] = (pixel[3] as f64 * coverage) as u8;
+                    pixel = blend(background, pixel);
+                }
+                pixel
+            };
+            ob.extend_from_slice(&new_pixel);
+        }
+    }
+
+    Some((ImageInfo::new(width, height, info.transparency), ob))
+}
+
+/// Apply the value of the EXIF `Orientation` tag (1 to 8)
+///
+/// None of the formats decoded by this library currently carry EXIF data,
+/// so this is for the images whose orientation is known from elsewhere.
+#[wasm_bindgen]
+pub fn apply_orientation(orientation: u8) -> bool {
+    match orientation {
+        1 => (),
+        2 => flip_horizontal(),
+        3 => rotate180(),
+        4 => flip_vertical(),
+        5 => transpose(),
+        6 => rotate90(),
+        7 => {
+            transpose();
+            rotate180();
+        }
+        8 => rotate270(),
+        _ => return false,
+    }
+    true
+}
+
+/// Mirror each line of the buffer in place
+pub fn flip_horizontal_buffer(info: &ImageInfo, ib: &mut [u8]) {
+    let stride = info.width as usize * MAGIC_NUMBER;
+    if stride == 0 {
+        return;
+    }
+    for line in ib.chunks_exact_mut(stride) {
+        let width = info.width as usize;
+        for x in 0..width / 2 {
+            swap_pixels(line, x, width - 1 - x);
+        }
+    }
+}
+
+/// Mirror the lines of the buffer in place
+pub fn flip_vertical_buffer(info: &ImageInfo, ib: &mut [u8]) {
+    let stride = info.width as usize * MAGIC_NUMBER;
+    let height = info.height as usize;
+    for y in 0..height / 2 {
+        let (upper, lower) = ib.split_at_mut((height - 1 - y) * stride);
+        upper[y * stride..(y + 1) * stride].swap_with_slice(&mut lower[..stride]);
+    }
+}
+
+/// Rotate the buffer 180 degrees in place
+pub fn rotate180_buffer(ib: &mut [u8]) {
+    let len = ib.len() / MAGIC_NUMBER;
+    for index in 0..len / 2 {
+        swap_pixels(ib, index, len - 1 - index);
+    }
+}
+
+/// Transpose the buffer in place and swap the width and height of the image
+pub fn transpose_buffer(info: &mut ImageInfo, ib: &mut [u8]) {
+    let width = info.width as usize;
+    let height = info.height as usize;
+    let len = width * height;
+
+    if width == height {
+        for y in 0..height {
+            for x in y + 1..width {
+                swap_pixels(ib, y * width + x, x * width + y);
+            }
+        }
+    } else if len > 2 {
+        // Follow the cycles of the permutation, the first and last pixels never move
+        let mut visited = vec![0u64; len.div_ceil(64)];
+        for start in 1..len - 1 {
+            if visited[start / 64] & (1 << (start % 64)) != 0 {
+                continue;
+            }
+            let mut index = start;
+            loop {
+                let next = (index % width) * height + index / width;
+                visited[next / 64] |= 1 << (next % 64);
+                if next == start {
+                    break;
+                }
+                swap_pixels(ib, start, next);
+                index = next;
+            }
+        }
+    }
+
+    info.width = height as u32;
+    info.height = width as u32;
+}
+
+#[inline]
+fn swap_pixels(ib: &mut [u8], a: usize, b: usize) {
+    for i in 0..MAGIC_NUMBER {
+        ib.swap(a * MAGIC_NUMBER + i, b * MAGIC_NUMBER + i);
+    }
+}
@@ -157,6 +157,13 @@ class App {
             }
         });
 
+        ($('#cropTrimButton') as HTMLButtonElement | null)?.addEventListener('click', () => {
+            if (this.validCanvas() !== null) {
+                const tolerance = parseInt(($('#cropTrimTolerance') as HTMLInputElement).value);
+                this.performAutoTrim(tolerance);
+            }
+        });
+
         for (const selector of '#cropX #cropY #cropWidth #cropHeight'.split(' ')) {
             ($(selector) as HTMLInputElement | null)?.addEventListener('input', (e) => {
                 CropDialog.updateHandle();
@@ -196,6 +203,7 @@ class App {
                 const width = parseInt(($('#scaleWidth') as HTMLInputElement).value);
                 const height = parseInt(($('#scaleHeight') as HTMLInputElement).value);
                 const scaleMode = parseInt(($('#scaleMode') as HTMLSelectElement).value);
+                libimage.set_linear_light(($('#scaleLinearLight') as HTMLInputElement | null)?.checked ?? false);
                 this.performScale(width, height, scaleMode);
             }
         });
@@ -240,31 +248,96 @@ class App {
 
         ($('#posterizeWscButton') as HTMLButtonElement | null)?.addEventListener('click', () => {
             if (this.validCanvas() !== null) {
-                const fsd = (($('#posterizeMode') as HTMLSelectElement | null)?.value ?? 0) != 0;
-                this.performPosterize(fsd, 6, 6, 6);
+                const mode = this.posterizeMode();
+                this.performPosterize(mode, 6, 6, 6);
             }
         });
 
         ($('#posterizeRgb565Button') as HTMLButtonElement | null)?.addEventListener('click', () => {
             if (this.validCanvas() !== null) {
-                const fsd = (($('#posterizeMode') as HTMLSelectElement | null)?.value ?? 0) != 0;
-                this.performPosterize(fsd, 32, 64, 32);
+                const mode = this.posterizeMode();
+                this.performPosterize(mode, 32, 64, 32);
             }
         });
 
         ($('#posterizeRgb555Button') as HTMLButtonElement | null)?.addEventListener('click', () => {
             if (this.validCanvas() !== null) {
-                const fsd = (($('#posterizeMode') as HTMLSelectElement | null)?.value ?? 0) != 0;
-                this.performPosterize(fsd, 32, 32, 32);
+                const mode = this.posterizeMode();
+                this.performPosterize(mode, 32, 32, 32);
             }
         });
 
         ($('#posterizeButton') as HTMLButtonElement | null)?.addEventListener('click', () => {
             if (this.validCanvas() !== null) {
-                const fsd = (($('#posterizeMode') as HTMLSelectElement | null)?.value ?? 0) != 0;
+                const mode = this.posterizeMode();
                 const bits = parseInt(($('#posterizeBpc') as HTMLInputElement | null)?.value ?? "") ?? 0;
                 const level = 1 << bits;
-                this.performPosterize(fsd, level, level, level);
+                this.performPosterize(mode, level, level, level);
+            }
+        });
+
+        ($('#quantizeButton') as HTMLButtonElement | null)?.addEventListener('click', () => {
+            if (this.validCanvas() !== null) {
+                const mode = this.posterizeMode();
+                const colors = parseInt(($('#quantizeColors') as HTMLInputElement | null)?.value ?? "") || 256;
+                const algorithm = parseInt(($('#quantizeAlgorithm') as HTMLSelectElement | null)?.value ?? '0') as libimage.QuantizeAlgorithm;
+                this.performQuantize(colors, algorithm, mode);
+            }
+        });
+
+        ($('#autoLevelsButton') as HTMLButtonElement | null)?.addEventListener('click', () => {
+            if (this.validCanvas() !== null) {
+                this.performAutoAdjust(() => libimage.auto_levels(0.5));
+            }
+        });
+
+        ($('#autoContrastButton') as HTMLButtonElement | null)?.addEventListener('click', () => {
+            if (this.validCanvas() !== null) {
+                this.performAutoAdjust(() => libimage.auto_contrast(0.5));
+            }
+        });
+
+        ($('#whiteBalanceGrayWorldButton') as HTMLButtonElement | null)?.addEventListener('click', () => {
+            if (this.validCanvas() !== null) {
+                this.performAutoAdjust(() => libimage.auto_white_balance(libimage.WhiteBalanceMode.GrayWorld));
+            }
+        });
+
+        ($('#whiteBalanceWhitePatchButton') as HTMLButtonElement | null)?.addEventListener('click', () => {
+            if (this.validCanvas() !== null) {
+                this.performAutoAdjust(() => libimage.auto_white_balance(libimage.WhiteBalanceMode.WhitePatch));
+            }
+        });
+
+        ($('#paletteButton') as HTMLButtonElement | null)?.addEventListener('click', () => {
+            if (this.validCanvas() !== null) {
+                const mode = this.posterizeMode();
+                const palette = parseInt(($('#paletteSelect') as HTMLSelectElement | null)?.value ?? '0');
+                const metric = parseInt(($('#paletteMetric') as HTMLSelectElement | null)?.value ?? '0') as libimage.DistanceMetric;
+                this.performApplyPalette(palette, metric, mode);
+            }
+        });
+
+        ($('#paletteFile') as HTMLInputElement | null)?.addEventListener('change', (e) => {
+            const file = ((e.target as HTMLInputElement)?.files ?? [])[0];
+            if (file !== null) {
+                const reader = new FileReader();
+                reader.addEventListener('load', (e) => {
+                    const result = e.target?.result;
+                    if (result instanceof ArrayBuffer) {
+                        const palette = libimage.parse_palette(new Uint8Array(result));
+                        if (palette === undefined) {
+                            alert('Unsupported palette file');
+                            return;
+                        }
+                        this.customPalette = palette;
+                        const select = $('#paletteSelect') as HTMLSelectElement | null;
+                        if (select !== null) {
+                            select.value = '-1';
+                        }
+                    }
+                });
+                reader.readAsArrayBuffer(file);
             }
         });
 
@@ -307,6 +380,13 @@ class App {
             }
         });
 
+        ($('#pixelRestoreButton') as HTMLButtonElement | null)?.addEventListener('click', () => {
+            if (this.validCanvas() !== null) {
+                const max_color_diff = parseInt(($('#pixelMaxError') as HTMLInputElement).value);
+                this.performPixelArtRestore(max_color_diff);
+            }
+        });
+
         ($('#pixelResetButton') as HTMLButtonElement | null)?.addEventListener('click', () => {
             if (this.validCanvas() !== null) {
                 PixelDialog.reset();
@@ -333,6 +413,25 @@ class App {
             }
         });
 
+        ($('#copyDataUriButton') as HTMLButtonElement | null)?.addEventListener('click', () => {
+            if (this.validCanvas() !== null) {
+                this.copyDataUri(libimage.ImageType.Png);
+            }
+        });
+
+        ($('#pasteDataUriButton') as HTMLButtonElement | null)?.addEventListener('click', () => {
+            const uri = prompt("Data URI");
+            if (uri === null || uri.trim().length === 0) {
+                return;
+            }
+            const canvas = $('#mainCanvas') as HTMLCanvasElement | null;
+            if (canvas === null) {
+                return;
+            }
+            new MainMenu().dismiss();
+            this.loadDataUri("image", canvas, uri.trim());
+        });
+
         ($('#fileLocal') as HTMLInputElement | null)?.addEventListener('change', (e) => {
             const file = ((e.target as HTMLInputElement)?.files ?? [])[0];
             if (file !== null) {
@@ -468,35 +567,54 @@ class App {
 
     loadImage(name: string, canvas: HTMLCanvasElement, blob: ArrayBuffer) {
         if (libimage.decode(new Uint8Array(blob))) {
-            const width = libimage.image_width();
-            const height = libimage.image_height();
-            console.log(`Loaded ${name} via WASM (${width} x ${height}) has_alpha: ${libimage.image_has_alpha()}`);
+            this.loadedViaWasm(name, canvas);
+        } else {
+            const imageType = 'image/png';
+            this.loadViaImg(name, canvas, "data:" + imageType + ";base64," + arrayBufferToBase64(blob));
+        }
+    }
+
+    loadDataUri(name: string, canvas: HTMLCanvasElement, uri: string) {
+        if (libimage.decode_data_uri(uri)) {
+            this.loadedViaWasm(name, canvas);
+        } else if (uri.startsWith('data:image/')) {
+            // PNG and other types are decoded by the browser
+            this.loadViaImg(name, canvas, uri);
+        } else {
+            alert("Invalid data URI");
+        }
+    }
+
+    loadedViaWasm(name: string, canvas: HTMLCanvasElement) {
+        const width = libimage.image_width();
+        const height = libimage.image_height();
+        console.log(`Loaded ${name} via WASM (${width} x ${height}) has_alpha: ${libimage.image_has_alpha()}`);
+        canvas.width = width;
+        canvas.height = height;
+        const ctx = canvas.getContext('2d');
+        if (ctx !== null) {
+            libimage.draw_to_canvas(ctx);
+        }
+        this.updateInfo(name, width, height);
+        Dialog.dismissAll();
+    }
+
+    loadViaImg(name: string, canvas: HTMLCanvasElement, src: string) {
+        const img = new Image();
+        img.src = src;
+        img.decode().then(() => {
+            const { width, height } = img;
+            console.log(`Loaded ${name} via IMG (${width} x ${height})`);
             canvas.width = width;
             canvas.height = height;
-            const ctx = canvas.getContext('2d');
-            if (ctx !== null) {
-                libimage.draw_to_canvas(ctx);
-            }
+            canvas.getContext('2d')?.drawImage(img, 0, 0);
             this.updateInfo(name, width, height);
             Dialog.dismissAll();
-        } else {
-            const img = new Image();
-            const imageType = 'image/png';
-            img.src = "data:" + imageType + ";base64," + arrayBufferToBase64(blob);
-            img.decode().then(() => {
-                const { width, height } = img;
-                console.log(`Loaded ${name} via IMG (${width} x ${height})`);
-                canvas.width = width;
-                canvas.height = height;
-                canvas.getContext('2d')?.drawImage(img, 0, 0);
-                this.updateInfo(name, width, height);
-                Dialog.dismissAll();
-                this.reflectCanvasToLib(canvas);
-            }).catch((reason) => {
-                alert("Unsupported file type");
-                console.log('Decode error', reason);
-            });
-        }
+            this.reflectCanvasToLib(canvas);
+        }).catch((reason) => {
+            alert("Unsupported file type");
+            console.log('Decode error', reason);
+        });
     }
 
     exportEncoded(type: libimage.ImageType) {
@@ -522,6 +640,21 @@ class App {
         }, 100);
     }
 
+    copyDataUri(type: libimage.ImageType) {
+        const uri = libimage.encode_data_uri(type);
+        if (uri === undefined) {
+            alert("ENCODE ERROR");
+            console.log('encode error');
+            return;
+        }
+        navigator.clipboard.writeText(uri).then(() => {
+            Dialog.dismissAll();
+        }).catch((reason) => {
+            alert("Could not copy to the clipboard");
+            console.log('Clipboard error', reason);
+        });
+    }
+
     // exportImage(canvas: HTMLCanvasElement) {
     //     const dataUrl = canvas.toDataURL('image/png');
     //     const tag = document.createElement('a') as HTMLAnchorElement;
@@ -587,6 +720,22 @@ class App {
         }
     }
 
+    performAutoTrim(tolerance: number) {
+        const canvas = this.validCanvas();
+        if (canvas === null) {
+            return;
+        }
+        const mode = libimage.image_has_alpha() ? libimage.TrimMode.Transparent : libimage.TrimMode.CornerColor;
+        const rect = libimage.auto_trim(tolerance, mode);
+        if (rect !== undefined) {
+            rect.free();
+            this.reflectLibToCanvas();
+            CropDialog.update();
+        } else {
+            alert('Nothing to trim');
+        }
+    }
+
     performScale(width: number, height: number, scaleMode: libimage.ScaleMode) {
         const canvas = this.validCanvas();
         if (canvas === null) {
@@ -613,12 +762,54 @@ class App {
         this.reflectLibToCanvas();
     }
 
-    performPosterize(fsd: boolean, red: number, green: number, blue: number) {
+    posterizeMode(): libimage.DitherMode {
+        return parseInt(($('#posterizeMode') as HTMLSelectElement | null)?.value ?? '0') as libimage.DitherMode;
+    }
+
+    performPosterize(mode: libimage.DitherMode, red: number, green: number, blue: number) {
+        const canvas = this.validCanvas();
+        if (canvas === null) {
+            return;
+        }
+        const serpentine = ($('#posterizeSerpentine') as HTMLInputElement | null)?.checked ?? false;
+        libimage.posterize(mode, serpentine, red, green, blue);
+        this.reflectLibToCanvas();
+    }
+
+    performQuantize(colors: number, algorithm: libimage.QuantizeAlgorithm, mode: libimage.DitherMode) {
+        const canvas = this.validCanvas();
+        if (canvas === null) {
+            return;
+        }
+        libimage.quantize(colors, algorithm, mode);
+        this.reflectLibToCanvas();
+    }
+
+    performAutoAdjust(adjust: () => boolean) {
+        const canvas = this.validCanvas();
+        if (canvas === null) {
+            return;
+        }
+        if (adjust()) {
+            this.reflectLibToCanvas();
+        }
+    }
+
+    private customPalette: Uint8Array | null = null;
+    performApplyPalette(palette: number, metric: libimage.DistanceMetric, mode: libimage.DitherMode) {
         const canvas = this.validCanvas();
         if (canvas === null) {
             return;
         }
-        libimage.posterize(fsd, red, green, blue);
+        if (palette < 0) {
+            if (this.customPalette === null) {
+                alert('No palette is loaded');
+                return;
+            }
+            libimage.apply_palette(this.customPalette, metric, mode);
+        } else {
+            libimage.apply_builtin_palette(palette as libimage.BuiltinPalette, metric, mode);
+        }
         this.reflectLibToCanvas();
     }
 
@@ -643,6 +834,20 @@ class App {
         }
     }
 
+    performPixelArtRestore(max_color_diff: number) {
+        const canvas = this.validCanvas();
+        if (canvas === null) { return; }
+        const grid = libimage.restore_pixel_art(max_color_diff);
+        if (grid !== undefined) {
+            grid.free();
+            ($('#pixelScale') as HTMLInputElement).value = '0';
+            this.reflectLibToCanvas();
+            PixelDialog.updateHandle();
+        } else {
+            alert('Failed to detect scale');
+        }
+    }
+
     snapshotSave() {
         libimage.snapshot_save();
     }
@@ -716,7 +921,8 @@ class CropDialog extends Dialog {
     onShow(): void {
         super.onShow();
         CropDialog.inShow = true;
-        CropDialog.isDark = libimage.image_is_dark(0xFF, 0x40, 64);
+        const statistics = libimage.image_statistics(64);
+        CropDialog.isDark = statistics[libimage.StatisticsChannel.Luminance * libimage.number_of_statistics() + libimage.Statistic.Mean] < 0x40;
         CropDialog.update();
     }
     onClose(): void {